@@ -0,0 +1,135 @@
+use std::path::{Component,Path,PathBuf};
+use std::io::{BufRead,BufReader,Read};
+use crate::bundle::{is_zstd,BUNDLE_FILES,BUNDLE_MANIFEST,BUNDLE_REMOVED};
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::compare::ChangeSet;
+use crate::portable_path;
+
+use async_trait::async_trait;
+
+#[derive(Debug)]
+pub struct Apply {
+    bundle_file: String,
+    base_dir: PathBuf,
+}
+
+impl Apply {
+    pub fn new( bundle_file: &str, base_dir: &Path ) -> Self {
+        Self {
+            bundle_file: bundle_file.to_string(),
+            base_dir: base_dir.to_owned(),
+        }
+    }
+
+    /// Calls `f` for every entry of the bundle, decompressing it if needed.
+    fn for_each_entry< F >( &self, mut f: F ) -> anyhow::Result< () >
+        where F: FnMut( &mut tar::Entry< '_, Box< dyn Read > > ) -> anyhow::Result< () >
+    {
+        let mut r = BufReader::new( std::fs::File::open( &self.bundle_file )? );
+        let r: Box< dyn Read > = if is_zstd( r.fill_buf()? ) {
+            Box::new( zstd::Decoder::with_buffer( r )? )
+        } else {
+            Box::new( r )
+        };
+        let mut archive = tar::Archive::new( r );
+        for entry in archive.entries()? {
+            f( &mut entry? )?;
+        }
+        Ok(())
+    }
+
+    /// Reads the manifest and the removed files, and checks every other entry before anything is written.
+    fn check( &self ) -> anyhow::Result< ( Checksums, Vec< PathBuf > ) > {
+        let mut manifest = None;
+        let mut removed = None;
+
+        self.for_each_entry( |entry| {
+            let path = entry.path()?.into_owned();
+            if path == Path::new( BUNDLE_MANIFEST ) {
+                manifest = Some( serde_json::from_reader( entry )? );
+            } else if path == Path::new( BUNDLE_REMOVED ) {
+                let encoded: Vec< String > = serde_json::from_reader( entry )?;
+                removed = Some( encoded.iter().map( |p| portable_path::decode( p ) ).collect::< anyhow::Result< Vec< PathBuf > > >()? );
+            } else if let Ok( rp ) = path.strip_prefix( BUNDLE_FILES ) {
+                // links could point anywhere, and later entries would be written through them
+                if entry.header().entry_type() != tar::EntryType::Regular {
+                    anyhow::bail!( "Refusing to apply {:?}, which is not a regular file", &path );
+                }
+                self.target_path( rp )?;
+            } else {
+                anyhow::bail!( "Unexpected entry {:?} in bundle", &path );
+            }
+            Ok(())
+        })?;
+
+        match ( manifest, removed ) {
+            ( Some( manifest ), Some( removed ) ) => Ok( ( manifest, removed ) ),
+            _ => anyhow::bail!( "Bundle is missing {} or {}", BUNDLE_MANIFEST, BUNDLE_REMOVED ),
+        }
+    }
+
+    /// Writes the files of a bundle that passed `check`.
+    fn unpack( &self ) -> anyhow::Result< () > {
+        let base_dir = self.base_dir.canonicalize()?;
+        self.for_each_entry( |entry| {
+            let path = entry.path()?.into_owned();
+            if let Ok( rp ) = path.strip_prefix( BUNDLE_FILES ) {
+                let target = self.target_path( rp )?;
+                if let Some( parent ) = target.parent() {
+                    std::fs::create_dir_all( parent )?;
+                    // the same check as `Entry::unpack_in`, which can not be used as the files are below BUNDLE_FILES
+                    if !parent.canonicalize()?.starts_with( &base_dir ) {
+                        anyhow::bail!( "Refusing to apply {:?} outside of {:?}", rp, &self.base_dir );
+                    }
+                }
+                entry.unpack( &target )?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Resolves a path from the bundle below the base dir, refusing anything that could escape it.
+    fn target_path( &self, rp: &Path ) -> anyhow::Result< PathBuf > {
+        if rp.as_os_str().is_empty() || rp.components().any( |c| !matches!( c, Component::Normal( _ ) ) ) {
+            anyhow::bail!( "Refusing to apply unsafe path {:?}", rp );
+        }
+        Ok( self.base_dir.join( rp ) )
+    }
+}
+
+#[async_trait]
+impl CommandAsync for Apply {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let ( manifest, removed ) = self.check()?;
+        for rp in removed.iter() {
+            self.target_path( rp )?;
+        }
+        self.unpack()?;
+
+        for rp in removed {
+            let target = self.base_dir.join( &rp );
+            match std::fs::remove_file( &target ) {
+                Err( e ) if e.kind() != std::io::ErrorKind::NotFound => return Err( e.into() ),
+                _ => {},
+            }
+        }
+
+        // verify the patched tree against the embedded manifest
+        let mut checksums = Checksums::new( manifest.algorithm() );
+        checksums.add_dir( &self.base_dir )?;
+        checksums.calculate_hashes( &self.base_dir )?;
+
+        let change_set = ChangeSet::new( &manifest, &checksums, None );
+        if !change_set.is_clean() {
+            for ( name, paths ) in [ ( "Changed", &change_set.changed ), ( "Removed", &change_set.removed ), ( "Added", &change_set.added ) ].iter() {
+                for p in paths.iter() {
+                    eprintln!( "{}: {}", name, portable_path::encode( p ) );
+                }
+            }
+            anyhow::bail!( "Verification after apply failed" );
+        }
+        println!( "Applied bundle. {} files verified.", checksums.len() );
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::{Component,Path,PathBuf};
+use std::fs::Metadata;
+use std::io::Read;
+use crate::checksums::*;
+
+/// Appended to the path of an archive to form the virtual directory containing its entries,
+/// e.g. `pkg.zip!/lib/a.dll`.
+pub const ARCHIVE_SEPARATOR: &str = "!";
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    pub fn from_path( path: &Path ) -> Option< Self > {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with( ".zip" ) {
+            Some( ArchiveKind::Zip )
+        } else if name.ends_with( ".tar" ) {
+            Some( ArchiveKind::Tar )
+        } else if name.ends_with( ".tar.gz" ) || name.ends_with( ".tgz" ) {
+            Some( ArchiveKind::TarGz )
+        } else if name.ends_with( ".tar.zst" ) || name.ends_with( ".tzst" ) {
+            Some( ArchiveKind::TarZst )
+        } else {
+            None
+        }
+    }
+}
+
+pub fn virtual_path( archive: &Path, inner: &Path ) -> PathBuf {
+    let mut name = archive.as_os_str().to_owned();
+    name.push( ARCHIVE_SEPARATOR );
+    PathBuf::from( name ).join( inner )
+}
+
+/// Hashes every file inside the archive at `fullpath`, without extracting it,
+/// and calls `f` with the path inside the archive, the size and the hash.
+pub fn for_each_entry< F >( fullpath: &Path, kind: ArchiveKind, mut f: F ) -> anyhow::Result< () >
+    where F: FnMut( &Path, u64, &str )
+{
+    let file = std::fs::File::open( fullpath )?;
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new( file )?;
+            for i in 0..zip.len() {
+                let mut zf = zip.by_index( i )?;
+                if zf.is_dir() {
+                    continue;
+                }
+                let name = match zf.enclosed_name() {
+                    Some( name ) => name.to_owned(),
+                    None => anyhow::bail!( "Unsafe entry {:?} in {:?}", zf.name(), fullpath ),
+                };
+                let size = zf.size();
+                let hash = hash_reader( &mut zf, None )?;
+                f( &name, size, &hash );
+            }
+        },
+        ArchiveKind::Tar => for_each_tar_entry( file, &mut f )?,
+        ArchiveKind::TarGz => for_each_tar_entry( flate2::read::GzDecoder::new( file ), &mut f )?,
+        ArchiveKind::TarZst => for_each_tar_entry( zstd::Decoder::new( file )?, &mut f )?,
+    }
+    Ok(())
+}
+
+fn for_each_tar_entry< R: Read, F >( r: R, f: &mut F ) -> anyhow::Result< () >
+    where F: FnMut( &Path, u64, &str )
+{
+    let mut archive = tar::Archive::new( r );
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        // like zip's `enclosed_name`, the entry must stay inside the archive's virtual directory
+        if path.components().any( |c| !matches!( c, Component::Normal( _ ) | Component::CurDir ) ) {
+            anyhow::bail!( "Unsafe entry {:?}", path );
+        }
+        // archives created via `tar -C dir .` prefix everything with `./`
+        let name: PathBuf = path.components().filter( |c| *c != Component::CurDir ).collect();
+        let size = entry.size();
+        let hash = hash_reader( &mut entry, None )?;
+        f( &name, size, &hash );
+    }
+    Ok(())
+}
+
+/// Calculates checksums for the contents of the archive at `path`.
+pub fn archive_checksums( path: &Path, kind: ArchiveKind ) -> anyhow::Result< Checksums > {
+    let mut checksums = Checksums::new( "sha1" );
+    checksums.header_mut().set_base_dir( path );
+    for_each_entry( path, kind, |name, size, hash| {
+        checksums.add( ChecksumsEntry::new( name, size, hash ) );
+    })?;
+    Ok( checksums )
+}
+
+/// Loads checksums from a manifest, or calculates them from an archive.
+pub fn load_checksums( filename: &str ) -> anyhow::Result< Checksums > {
+    let path = Path::new( filename );
+    match ArchiveKind::from_path( path ) {
+        Some( kind ) if path.is_file() => archive_checksums( path, kind ),
+        _ => Checksums::load( filename ),
+    }
+}
+
+/// Adds the archive at `base_dir`/`rp` to `checksums`, either as a virtual directory of its (already hashed) entries,
+/// or, if it can not be read, as an ordinary file.
+pub fn add_archive( checksums: &mut Checksums, base_dir: &Path, rp: &Path, metadata: &Metadata ) {
+    let kind = match ArchiveKind::from_path( rp ) {
+        Some( kind ) => kind,
+        None => {
+            checksums.add( ChecksumsEntry::from_metadata( rp, metadata ) );
+            return;
+        },
+    };
+    let mut entries = Vec::new();
+    let result = for_each_entry( &base_dir.join( rp ), kind, |name, size, hash| {
+        entries.push( ChecksumsEntry::new( &virtual_path( rp, name ), size, hash ) );
+    });
+    match result {
+        Ok( _ ) => {
+            for e in entries {
+                checksums.add( e );
+            }
+        },
+        Err( e ) => {
+            eprintln!( "Treating {:?} as file. Failed reading archive: {}", rp, e );
+            checksums.add( ChecksumsEntry::from_metadata( rp, metadata ) );
+        },
+    }
+}
@@ -0,0 +1,365 @@
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::io::{Read,Write};
+use std::path::Path;
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use crate::checksums::*;
+use crate::manifest_format::{ManifestReader,ManifestWriter};
+use crate::portable_path;
+
+pub const BINARY_MAGIC: &[u8] = b"FCMANIF\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 64;
+/// The size of a record without the hash.
+const RECORD_LEN: usize = 32;
+
+const FLAG_HAS_HASH: u32 = 1;
+const FLAG_HAS_MTIME: u32 = 2;
+
+#[derive(Deserialize,Serialize)]
+struct Meta {
+    header: ManifestHeader,
+    algorithm: String,
+}
+
+#[derive(Debug)]
+enum Storage {
+    Mapped( Mmap ),
+    Owned( Vec< u8 > ),
+}
+
+impl Storage {
+    fn bytes( &self ) -> &[u8] {
+        match self {
+            Storage::Mapped( m ) => m,
+            Storage::Owned( v ) => v,
+        }
+    }
+}
+
+fn read_u32( data: &[u8], offset: usize ) -> u32 {
+    u32::from_le_bytes( data[ offset..offset+4 ].try_into().unwrap_or_default() )
+}
+
+fn read_u64( data: &[u8], offset: usize ) -> u64 {
+    u64::from_le_bytes( data[ offset..offset+8 ].try_into().unwrap_or_default() )
+}
+
+fn to_hex( bytes: &[u8] ) -> String {
+    bytes.iter().map( |b| format!( "{:02X}", b ) ).collect()
+}
+
+fn from_hex( hex: &str ) -> Option< Vec< u8 > > {
+    if !hex.len().is_multiple_of( 2 ) {
+        return None;
+    }
+    ( 0..hex.len() ).step_by( 2 )
+        .map( |i| u8::from_str_radix( hex.get( i..i+2 )?, 16 ).ok() )
+        .collect()
+}
+
+/// A binary manifest, either memory mapped or in memory.
+///
+/// A mapped file must not be modified while it is in use.
+#[derive(Debug)]
+pub struct BinaryManifest {
+    storage: Storage,
+    hash_len: usize,
+    count: usize,
+    total_size: u64,
+    meta_len: usize,
+    table_offset: usize,
+    pool_offset: usize,
+    pool_len: usize,
+}
+
+impl BinaryManifest {
+    /// True if the file at `path` starts like a binary manifest.
+    pub fn is_binary_file( path: &Path ) -> bool {
+        let mut magic = [ 0u8; 8 ];
+        match std::fs::File::open( path ) {
+            Ok( mut f ) => f.read_exact( &mut magic ).is_ok() && magic == BINARY_MAGIC,
+            Err( _ ) => false,
+        }
+    }
+
+    pub fn open( path: &Path ) -> anyhow::Result< Self > {
+        let f = std::fs::File::open( path )?;
+        // safety: the file must not change while it is mapped, see `BinaryManifest`
+        let mmap = unsafe { Mmap::map( &f )? };
+        Self::new( Storage::Mapped( mmap ) )
+    }
+
+    pub fn from_bytes( data: Vec< u8 > ) -> anyhow::Result< Self > {
+        Self::new( Storage::Owned( data ) )
+    }
+
+    fn new( storage: Storage ) -> anyhow::Result< Self > {
+        let data = storage.bytes();
+        if data.len() < HEADER_LEN || !data.starts_with( BINARY_MAGIC ) {
+            anyhow::bail!( "Not a binary manifest" );
+        }
+        let version = read_u32( data, 8 );
+        if version != VERSION {
+            anyhow::bail!( "Binary manifest version {} is not supported, only {}", version, VERSION );
+        }
+        let hash_len = read_u32( data, 12 ) as usize;
+        let count = read_u64( data, 16 ) as usize;
+        let total_size = read_u64( data, 24 );
+        let meta_len = read_u64( data, 32 ) as usize;
+        let table_offset = read_u64( data, 40 ) as usize;
+        let pool_offset = read_u64( data, 48 ) as usize;
+        let pool_len = read_u64( data, 56 ) as usize;
+
+        let table_end = count.checked_mul( RECORD_LEN + hash_len ).and_then( |l| l.checked_add( table_offset ) );
+        let pool_end = pool_offset.checked_add( pool_len );
+        let meta_end = HEADER_LEN.checked_add( meta_len );
+        match ( table_end, pool_end, meta_end ) {
+            ( Some( t ), Some( p ), Some( m ) ) if t <= data.len() && p <= data.len() && m <= data.len() => {},
+            _ => anyhow::bail!( "Binary manifest is truncated" ),
+        }
+
+        Ok( Self {
+            storage,
+            hash_len,
+            count,
+            total_size,
+            meta_len,
+            table_offset,
+            pool_offset,
+            pool_len,
+        })
+    }
+
+    /// The header and the algorithm.
+    pub fn meta( &self ) -> anyhow::Result< ( ManifestHeader, String ) > {
+        let meta: Meta = serde_json::from_slice( &self.storage.bytes()[ HEADER_LEN..HEADER_LEN+self.meta_len ] )?;
+        Ok( ( meta.header, meta.algorithm ) )
+    }
+
+    pub fn len( &self ) -> usize {
+        self.count
+    }
+
+    pub fn total_size( &self ) -> u64 {
+        self.total_size
+    }
+
+    fn record( &self, i: usize ) -> &[u8] {
+        let record_len = RECORD_LEN + self.hash_len;
+        let start = self.table_offset + i * record_len;
+        &self.storage.bytes()[ start..start+record_len ]
+    }
+
+    fn path_bytes( &self, record: &[u8] ) -> &[u8] {
+        let offset = read_u64( record, 0 ) as usize;
+        let len = read_u32( record, 8 ) as usize;
+        let pool = &self.storage.bytes()[ self.pool_offset..self.pool_offset+self.pool_len ];
+        offset.checked_add( len ).and_then( |end| pool.get( offset..end ) ).unwrap_or_default()
+    }
+
+    pub fn entry( &self, i: usize ) -> ChecksumsEntry {
+        let record = self.record( i );
+        let flags = read_u32( record, 12 );
+        let size = read_u64( record, 16 );
+        let hash = if flags & FLAG_HAS_HASH != 0 {
+            to_hex( &record[ RECORD_LEN.. ] )
+        } else {
+            String::new()
+        };
+        let mut entry = ChecksumsEntry::new( &portable_path::from_bytes( self.path_bytes( record ) ), size, &hash );
+        if flags & FLAG_HAS_MTIME != 0 {
+            entry.set_mtime( read_u64( record, 24 ) as i64 );
+        }
+        entry
+    }
+
+    /// Decodes all entries.
+    pub fn entries( &self ) -> Vec< ChecksumsEntry > {
+        ( 0..self.count ).map( |i| self.entry( i ) ).collect()
+    }
+
+    pub fn find( &self, path: &Path ) -> Option< ChecksumsEntry > {
+        let key = portable_path::to_bytes( path );
+        let ( mut low, mut high ) = ( 0, self.count );
+        while low < high {
+            let mid = low + ( high - low ) / 2;
+            match self.path_bytes( self.record( mid ) ).cmp( &key ) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Some( self.entry( mid ) ),
+            }
+        }
+        None
+    }
+}
+
+/// A compact manifest format that is used in place, without parsing it first.
+///
+/// All numbers are little endian. The file starts with a fixed header of `HEADER_LEN` bytes:
+/// magic, version, hash length, entry count, total size, the length of the meta data,
+/// and the offsets of the entry table and the path pool.
+/// The meta data follows the header, it is the `ManifestHeader` and algorithm as JSON.
+/// The table has one fixed width record per entry, sorted by the raw bytes of the path,
+/// so entries can be found with a binary search.
+/// The pool holds the raw bytes of all paths, referenced by offset and length from the records.
+pub struct BinaryFormat;
+
+impl ManifestReader for BinaryFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut data = Vec::new();
+        r.read_to_end( &mut data )?;
+        Checksums::from_binary( BinaryManifest::from_bytes( data )? )
+    }
+}
+
+impl ManifestWriter for BinaryFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        let entries: Vec< _ > = checksums.saved_entries().into_iter()
+            .map( |e| ( portable_path::to_bytes( e.path() ), e ) )
+            .collect();
+        let total_size: u64 = entries.iter().map( |( _, e )| e.size() ).sum();
+
+        let hash_len = entries.iter().find( |( _, e )| e.has_hash() ).map( |( _, e )| e.hash().len() / 2 ).unwrap_or( 0 );
+        let meta = serde_json::to_vec( &Meta {
+            header: checksums.saved_header(),
+            algorithm: checksums.algorithm().to_string(),
+        })?;
+        let table_offset = HEADER_LEN + meta.len();
+        let pool_offset = table_offset + entries.len() * ( RECORD_LEN + hash_len );
+        let pool_len: usize = entries.iter().map( |( p, _ )| p.len() ).sum();
+
+        let mut header = Vec::with_capacity( HEADER_LEN );
+        header.extend_from_slice( BINARY_MAGIC );
+        header.extend_from_slice( &VERSION.to_le_bytes() );
+        header.extend_from_slice( &( hash_len as u32 ).to_le_bytes() );
+        for n in [ entries.len() as u64, total_size, meta.len() as u64, table_offset as u64, pool_offset as u64, pool_len as u64 ].iter() {
+            header.extend_from_slice( &n.to_le_bytes() );
+        }
+        w.write_all( &header )?;
+        w.write_all( &meta )?;
+
+        let mut path_offset = 0u64;
+        for ( p, e ) in entries.iter() {
+            let mut flags = 0;
+            let hash = if e.has_hash() {
+                flags |= FLAG_HAS_HASH;
+                match from_hex( e.hash() ) {
+                    Some( hash ) if hash.len() == hash_len => hash,
+                    _ => anyhow::bail!( "Can not store hash {:?} of {:?} in a binary manifest", e.hash(), e.path() ),
+                }
+            } else {
+                vec![ 0; hash_len ]
+            };
+            if e.mtime().is_some() {
+                flags |= FLAG_HAS_MTIME;
+            }
+            w.write_all( &path_offset.to_le_bytes() )?;
+            w.write_all( &( p.len() as u32 ).to_le_bytes() )?;
+            w.write_all( &flags.to_le_bytes() )?;
+            w.write_all( &e.size().to_le_bytes() )?;
+            w.write_all( &e.mtime().unwrap_or( 0 ).to_le_bytes() )?;
+            w.write_all( &hash )?;
+            path_offset += p.len() as u64;
+        }
+        for ( p, _ ) in entries.iter() {
+            w.write_all( p )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "A9993E364706816ABA3E25717850C26C9CD0D89D";
+
+    fn sample() -> Checksums {
+        let mut checksums = Checksums::new( "sha1" );
+        // `a.txt` sorts before `a/b` by bytes, but after it by components
+        checksums.add( ChecksumsEntry::new( Path::new( "a/b" ), 3, HASH ) );
+        let mut e = ChecksumsEntry::new( Path::new( "a.txt" ), 5, HASH );
+        e.set_mtime( 1600000000 );
+        checksums.add( e );
+        checksums.add( ChecksumsEntry::new( Path::new( "unhashed" ), 7, "" ) );
+        checksums
+    }
+
+    fn write( checksums: &Checksums ) -> Vec< u8 > {
+        let mut data = Vec::new();
+        BinaryFormat.write( checksums, &mut data ).unwrap();
+        data
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let binary = BinaryManifest::from_bytes( write( &sample() ) ).unwrap();
+        assert_eq!( binary.len(), 3 );
+        assert_eq!( binary.total_size(), 15 );
+        assert_eq!( binary.meta().unwrap().1, "sha1" );
+
+        let entries = binary.entries();
+        let paths: Vec< &Path > = entries.iter().map( |e| e.path().as_path() ).collect();
+        assert_eq!( paths, vec![ Path::new( "a.txt" ), Path::new( "a/b" ), Path::new( "unhashed" ) ] );
+        assert_eq!( entries[ 0 ].hash(), HASH );
+        assert_eq!( entries[ 0 ].mtime(), Some( 1600000000 ) );
+        assert_eq!( entries[ 1 ].mtime(), None );
+        assert!( !entries[ 2 ].has_hash() );
+    }
+
+    #[test]
+    fn find_uses_byte_order() {
+        let binary = BinaryManifest::from_bytes( write( &sample() ) ).unwrap();
+        for ( path, size ) in [ ( "a.txt", 5 ), ( "a/b", 3 ), ( "unhashed", 7 ) ].iter() {
+            assert_eq!( binary.find( Path::new( path ) ).map( |e| e.size() ), Some( *size ), "{}", path );
+        }
+        assert!( binary.find( Path::new( "a" ) ).is_none() );
+        assert!( binary.find( Path::new( "zzz" ) ).is_none() );
+        assert!( BinaryManifest::from_bytes( write( &Checksums::new( "sha1" ) ) ).unwrap().find( Path::new( "a" ) ).is_none() );
+    }
+
+    #[test]
+    fn rejects_foreign_and_newer_data() {
+        assert!( BinaryManifest::from_bytes( b"{\"algorithm\":\"sha1\"}".to_vec() ).is_err() );
+        let mut data = write( &sample() );
+        data[ 8 ] = 2;
+        assert!( BinaryManifest::from_bytes( data ).is_err() );
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = write( &sample() );
+        for len in [ 0, 8, HEADER_LEN - 1, HEADER_LEN, data.len() - 1 ].iter() {
+            assert!( BinaryManifest::from_bytes( data[ ..*len ].to_vec() ).is_err(), "{} bytes", len );
+        }
+    }
+
+    #[test]
+    fn rejects_overflowing_counts() {
+        let mut data = write( &sample() );
+        data[ 16..24 ].copy_from_slice( &u64::MAX.to_le_bytes() );
+        assert!( BinaryManifest::from_bytes( data ).is_err() );
+        let mut data = write( &sample() );
+        data[ 48..56 ].copy_from_slice( &u64::MAX.to_le_bytes() );
+        assert!( BinaryManifest::from_bytes( data ).is_err() );
+    }
+
+    #[test]
+    fn bad_path_references_give_empty_paths() {
+        let mut data = write( &sample() );
+        let table_offset = read_u64( &data, 40 ) as usize;
+        data[ table_offset..table_offset+8 ].copy_from_slice( &u64::MAX.to_le_bytes() );
+        let binary = BinaryManifest::from_bytes( data ).unwrap();
+        assert_eq!( binary.entry( 0 ).path(), Path::new( "" ) );
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!( from_hex( "00fF10" ), Some( vec![ 0x00, 0xFF, 0x10 ] ) );
+        assert_eq!( from_hex( "abc" ), None );
+        assert_eq!( from_hex( "zz" ), None );
+        assert_eq!( to_hex( &[ 0xAB, 0x01 ] ), "AB01" );
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path,PathBuf};
+use std::io::Write;
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::compare::ChangeSet;
+use crate::portable_path;
+
+use async_trait::async_trait;
+
+/// Name of the new manifest inside a bundle.
+pub const BUNDLE_MANIFEST: &str = "manifest.json";
+/// Name of the list of removed files inside a bundle.
+pub const BUNDLE_REMOVED: &str = "removed.json";
+/// Directory containing the added and changed files inside a bundle.
+pub const BUNDLE_FILES: &str = "files";
+
+const ZSTD_MAGIC: [u8; 4] = [ 0x28, 0xb5, 0x2f, 0xfd ];
+
+pub fn is_zstd( header: &[u8] ) -> bool {
+    header.starts_with( &ZSTD_MAGIC )
+}
+
+#[derive(Debug)]
+pub struct Bundle {
+    checksum_file_old: String,
+    base_dir: PathBuf,
+    bundle_file: String,
+    zstd: bool,
+}
+
+impl Bundle {
+    pub fn new( checksum_file_old: &str, base_dir: &Path, bundle_file: &str ) -> Self {
+        Self {
+            checksum_file_old: checksum_file_old.to_string(),
+            base_dir: base_dir.to_owned(),
+            bundle_file: bundle_file.to_string(),
+            zstd: false,
+        }
+    }
+
+    pub fn set_zstd( &mut self, zstd: bool ) {
+        self.zstd = zstd;
+    }
+
+    fn write_bundle< W: Write >( &self, w: W, new_checksums: &Checksums, change_set: &ChangeSet ) -> anyhow::Result< W > {
+        let mut builder = tar::Builder::new( w );
+
+        let manifest = serde_json::to_vec( new_checksums )?;
+        append_data( &mut builder, BUNDLE_MANIFEST, &manifest )?;
+        let removed: Vec< String > = change_set.removed.iter().map( |p| portable_path::encode( p ) ).collect();
+        let removed = serde_json::to_vec( &removed )?;
+        append_data( &mut builder, BUNDLE_REMOVED, &removed )?;
+
+        for p in change_set.added.iter().chain( change_set.changed.iter() ) {
+            let mut name = PathBuf::from( BUNDLE_FILES );
+            name.push( p );
+            builder.append_path_with_name( self.base_dir.join( p ), &name )?;
+        }
+
+        Ok( builder.into_inner()? )
+    }
+}
+
+fn append_data< W: Write >( builder: &mut tar::Builder< W >, name: &str, data: &[u8] ) -> anyhow::Result< () > {
+    let mut header = tar::Header::new_gnu();
+    header.set_size( data.len() as u64 );
+    header.set_mode( 0o644 );
+    builder.append_data( &mut header, name, data )?;
+    Ok(())
+}
+
+#[async_trait]
+impl CommandAsync for Bundle {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let old_checksums = Checksums::load( &self.checksum_file_old )?;
+        let mut new_checksums = Checksums::new( old_checksums.algorithm() );
+
+        new_checksums.add_dir( &self.base_dir )?;
+        new_checksums.calculate_hashes( &self.base_dir )?;
+
+        let change_set = ChangeSet::new( &old_checksums, &new_checksums, None );
+        println!(
+            "Bundling {} added and {} changed files, {} removed.",
+            change_set.added.len(), change_set.changed.len(), change_set.removed.len()
+        );
+
+        let f = std::fs::File::create( &self.bundle_file )?;
+        if self.zstd {
+            let encoder = zstd::Encoder::new( f, 0 )?;
+            self.write_bundle( encoder, &new_checksums, &change_set )?.finish()?;
+        } else {
+            self.write_bundle( f, &new_checksums, &change_set )?;
+        }
+        Ok(())
+    }
+}
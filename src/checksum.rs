@@ -1,21 +1,21 @@
 use std::error::Error;
+use std::fmt;
 use walkdir::WalkDir;
-use std::{fmt,fs};
 use std::path::{Path,PathBuf};
-use std::io::Read;
+use crate::archive::add_archive;
 use crate::checksums::*;
 use crate::command_async::CommandAsync;
+use crate::history::History;
+use crate::manifest::PathFilter;
+use crate::manifest_format::{Compression,ManifestFormat};
 use crate::message::Message;
-
-use indicatif::{MultiProgress,ProgressBar,ProgressStyle};
-use std::sync::mpsc::channel;
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
-
-use rayon::prelude::*;
+use crate::progress::{Progress,ProgressMode,ScanProgress};
+use crate::summary::{Outcome,Summary};
 
 use async_trait::async_trait;
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum ChecksumError {
     Generic( String ),
 }
@@ -32,10 +32,22 @@ impl Error for ChecksumError {
     }
 }
 
+/// The number of files hashed in parallel, unless configured.
+const DEFAULT_THREADS: usize = 16;
+
 #[derive(Debug)]
 pub struct Checksum {
     checksum_file: String,
     base_dir: PathBuf,
+    descend_archives: bool,
+    history_dir: Option< PathBuf >,
+    compression: Option< Compression >,
+    canonical: bool,
+    progress: ProgressMode,
+    progress_fd: Option< i32 >,
+    algorithm: String,
+    threads: usize,
+    filter: PathFilter,
 }
 
 impl Checksum {
@@ -43,129 +55,142 @@ impl Checksum {
         Self {
             checksum_file: checksum_file.to_string(),
             base_dir: base_dir.to_owned(),
+            descend_archives: false,
+            history_dir: None,
+            compression: None,
+            canonical: false,
+            progress: ProgressMode::Bars,
+            progress_fd: None,
+            algorithm: "sha1".to_string(),
+            threads: DEFAULT_THREADS,
+            filter: PathFilter::default(),
         }
     }
+
+    pub fn set_descend_archives( &mut self, descend_archives: bool ) {
+        self.descend_archives = descend_archives;
+    }
+
+    pub fn set_history_dir( &mut self, history_dir: &Path ) {
+        self.history_dir = Some( history_dir.to_owned() );
+    }
+
+    /// Overrides the compression chosen by the extension of the checksum file.
+    pub fn set_compression( &mut self, compression: Compression ) {
+        self.compression = Some( compression );
+    }
+
+    pub fn set_canonical( &mut self, canonical: bool ) {
+        self.canonical = canonical;
+    }
+
+    pub fn set_progress( &mut self, progress: ProgressMode ) {
+        self.progress = progress;
+    }
+
+    /// Writes JSON progress to `progress_fd` instead of stderr.
+    pub fn set_progress_fd( &mut self, progress_fd: i32 ) {
+        self.progress_fd = Some( progress_fd );
+    }
+
+    pub fn set_algorithm( &mut self, algorithm: &str ) {
+        self.algorithm = algorithm.to_string();
+    }
+
+    pub fn set_threads( &mut self, threads: usize ) {
+        self.threads = threads.max( 1 );
+    }
+
+    /// Only records the files `filter` matches.
+    pub fn set_filter( &mut self, filter: PathFilter ) {
+        self.filter = filter;
+    }
 }
 
 #[async_trait]
 impl CommandAsync for Checksum {
     async fn run( &mut self ) -> anyhow::Result<()> {
-
-        // :TODO: make configurable
-        rayon::ThreadPoolBuilder::new().num_threads(8).build_global().unwrap();
-
-        let mut checksums = Checksums::new( "sha1" );
+        let mut summary = Summary::new( &[ Outcome::Hashed, Outcome::Errored ] );
+        if self.algorithm != "sha1" {
+            anyhow::bail!( "Calculating {} hashes is not supported", self.algorithm );
+        }
+        let mut checksums = Checksums::new( &self.algorithm );
+        checksums.set_canonical( self.canonical );
+        checksums.header_mut().set_base_dir( &self.base_dir );
+        checksums.header_mut().set_option( "descend_archives", &self.descend_archives.to_string() );
+        checksums.header_mut().set_option( "symlinks", "skip" );
+        let ( include, exclude ) = self.filter.describe();
+        for ( name, patterns ) in [ ( "include", include ), ( "exclude", exclude ) ].iter() {
+            if !patterns.is_empty() {
+                checksums.header_mut().set_option( name, patterns );
+            }
+        }
 
         
-        let bar = ProgressBar::new( 1_000_000u64 );
-        let spinner_style = ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .template("{prefix:.bold.dim} {spinner} {wide_msg} {pos} files found.");
-
-        bar.set_style(spinner_style.clone());
+        let scan = ScanProgress::new( self.progress );
 
         for e in WalkDir::new( &self.base_dir ) {
             match e {
                 Ok( e ) => {
                     match e.metadata() {
                         Ok( m ) => if m.is_file() {
-                            bar.inc( 1 );
                             let rp = e.path().strip_prefix( &self.base_dir )?;
-                            let ce = ChecksumsEntry::new( rp, m.len(), "" );
-                            checksums.add( ce );
+                            if !self.filter.matches( rp ) {
+                                continue;
+                            }
+                            scan.inc();
+                            if self.descend_archives {
+                                add_archive( &mut checksums, &self.base_dir, rp, &m );
+                            } else {
+                                let ce = ChecksumsEntry::from_metadata( rp, &m );
+                                checksums.add( ce );
+                            }
                         },
-                        Err( e ) => {
+                        Err( _e ) => {
 //                            return Err( Box::new( ChecksumError::Generic( String::from( "Missing metadata" ) ) ) );
                         },        
                     };
                 },
-                Err( e ) => {
+                Err( _e ) => {
 //                    return Err( Box::new( ChecksumError::Generic( String::from( "WalkDir error" ) ) ) );
                 },
             }
         };
+        scan.finish();
 
 //        dbg!( &checksums );
-        println!( "Calculating checksums for {} files. {} bytes total.", checksums.len(), checksums.total_size() );
-
-        let bar = ProgressBar::new( checksums.len() as u64 );
-
-        bar.set_style(
-            ProgressStyle::default_bar()
-            .template( "{spinner:.green} Calculating checksums [{wide_bar:.cyan/blue}] {pos}/{len} {percent}% ETA: ~{eta}" )
-        );
+        if self.progress != ProgressMode::Quiet {
+            println!( "Calculating checksums for {} files. {} bytes total.", checksums.len(), checksums.total_size() );
+        }
 
         // handle progress update in thread, so we can let rayon do the work management
-        let (tx,rx) = channel();
-        let total_size = checksums.total_size();
-        let total_files = checksums.len() as u64;
-        let watcher = tokio::spawn(async move {
-            let mut keep_running = true;
-            let mut delay = 20;
-            let mut multi_bar = MultiProgress::new();
-            let bar_size = ProgressBar::new( total_size );
-//            let bar_size = multi_bar.add( bar_size );
-            bar_size.set_style(
-                ProgressStyle::default_bar()
-                .template( "{spinner:.green} Calculating checksums [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} at {bytes_per_sec} bytes/s {percent}% ETA: ~{eta_precise}" )
-            );
-            let bar_files = ProgressBar::new( total_files );
-//            let bar_files = multi_bar.add( bar_files );
-            bar_files.set_style(
-                ProgressStyle::default_bar()
-                .template( "{spinner:.green} Calculating checksums [{wide_bar:.cyan/blue}] {pos}/{len} {percent}% ETA: ~{eta}" )
-            );
-
-            while keep_running {
-                tokio::time::delay_for( std::time::Duration::from_millis(delay) ).await;
-                match rx.try_recv() {
-                    Ok( msg ) => {
-                        match msg {
-                            Message::Started( total_size, total_files ) => {
-                                bar_size.set_length( total_size as u64 );
-                                bar_files.set_length( total_files as u64 );
-                            },
-                            Message::Progress( size ) => {
-                                bar_size.inc( size as u64 );
-                            },
-                            Message::FileDone => {
-//                                bar_files.inc( 1 );   // :TODO: MultiProgress currently seems broken :(
-                            },
-                            Message::Done => {
-                                keep_running = false;
-                            },
-                            m => {
-                                dbg!(&m);
-                            },
-                        }
-                        delay = 1;      // if we got a mesage we try again fast
-                    },
-                    Err( _e ) => {
-                        delay = 2000;   // if we didn't get a message we can sleep for a bit
-                    },
-                }
-//                dbg!(&delay);
-            }; // while keep_running
-            multi_bar.join();
-        });
+        let ( tx, progress ) = Progress::new( self.progress, self.progress_fd, "Calculating checksums", self.threads ).spawn();
 
-        tx.send( Message::Started( checksums.total_size(), checksums.len() as u64 ) )?;
+        // archive members were already hashed while scanning
+        let ( hash_size, hash_files ) = checksums.entries().iter()
+            .filter( |e| !e.has_hash() )
+            .fold( ( 0, 0 ), |( size, files ), e| ( size + e.size(), files + 1 ) );
+        tx.send( Message::Started( hash_size, hash_files ) )?;
 
         let algorithm = checksums.algorithm().to_string();
         let pool = rayon::ThreadPoolBuilder::new()
-                    .num_threads( 16 )
+                    .num_threads( self.threads )
                     .build()
                     .unwrap();
         let entries = checksums.entries_mut();
         let base_dir = self.base_dir.clone();
         let ctx = tx.clone();
         pool.scope(move |s| {
-            for e in entries.into_iter() {
+            for e in entries.iter_mut().filter( |e| !e.has_hash() ) {
                 let tx = ctx.clone();
                 let base_dir = base_dir.clone();
                 let algorithm = algorithm.clone();
                 s.spawn(move |_| {
-                    e.calculate_hash( &base_dir, &algorithm, Some( tx ) );
+                    let result = match e.calculate_hash( &base_dir, &algorithm, Some( tx.clone() ) ) {
+                        Ok( _ ) => Message::Success,
+                        Err( err ) => Message::Failure( e.path().to_owned(), err.to_string() ),
+                    };
+                    let _ = tx.send( result );
                 });
             }
         });
@@ -176,10 +201,35 @@ impl CommandAsync for Checksum {
                 e.calculate_hash( &self.base_dir, &algorithm, Some( tx ) );
             });
             */
-        tx.send( Message::Done );
+        tx.send( Message::Done )?;
+        let _ = progress.join();
+        for e in checksums.entries() {
+            if e.has_hash() {
+                summary.add( Outcome::Hashed, e.path(), e.size() );
+                summary.add_hashed( e.size() );
+            } else {
+                summary.add( Outcome::Errored, e.path(), e.size() );
+            }
+        }
+        summary.print( false );
+        // a manifest without these hashes would report them as changed forever
+        let errored = summary.count( Outcome::Errored );
+        if errored > 0 {
+            anyhow::bail!( "Could not hash {} files, not writing {}", errored, self.checksum_file );
+        }
 //        dbg!( &checksums );
-        checksums.save( &self.checksum_file );
-        tokio::join!( watcher );
+        match self.compression {
+            Some( compression ) => {
+                let format = ManifestFormat::from_path( Path::new( &self.checksum_file ) );
+                checksums.save_with( &self.checksum_file, format, compression )?;
+            },
+            None => checksums.save( &self.checksum_file )?,
+        }
+        if let Some( history_dir ) = &self.history_dir {
+            let mut history = History::open( history_dir )?;
+            let id = history.record( &checksums, &self.base_dir.to_string_lossy() )?;
+            println!( "Recorded snapshot {} in {:?}", id, history_dir );
+        }
         Ok(())
     }
 }
@@ -1,19 +1,33 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap,HashMap};
 use std::path::{Path,PathBuf};
-use std::error::Error;
-use serde::{Deserialize, Serialize};
+use std::fs::Metadata;
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize, Serializer};
+use filetime::FileTime;
 use rayon::prelude::*;
-use std::io::Read;
 use sha1::Sha1;
+use walkdir::WalkDir;
+use crate::binary_manifest::BinaryManifest;
+use crate::manifest_format::{Compression,ManifestFormat};
 use crate::message::Message;
+use crate::path_matching::PathMatching;
+use crate::portable_path;
+use crate::sqlite_manifest;
 
-use std::io::BufReader;
+use anyhow::Context;
+use std::io::{BufRead,BufReader,BufWriter,Read,Write};
 use std::sync::mpsc::Sender;
 
-#[derive(Debug,Deserialize,Serialize)]
+#[derive(Debug,Clone,Deserialize,Serialize)]
 pub struct ChecksumsEntry {
+    #[serde(with = "crate::portable_path")]
     path: PathBuf,
     size: u64,
     hash: String,
+    /// Modification time in seconds since the unix epoch, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mtime: Option< i64 >,
 }
 
 impl ChecksumsEntry {
@@ -24,54 +38,53 @@ impl ChecksumsEntry {
     ) -> Self {
         Self {
             path: path.to_owned(),
-            size: size,
+            size,
             hash: hash.to_string(),
+            mtime: None,
         }
     }
 
+    pub fn from_metadata( path: &Path, metadata: &Metadata ) -> Self {
+        let mut entry = Self::new( path, metadata.len(), "" );
+        entry.mtime = Some( FileTime::from_last_modification_time( metadata ).unix_seconds() );
+        entry
+    }
+
     pub fn set_hash(&mut self, hash: &str) {
         self.hash = hash.to_string();
     }
 
     pub fn calculate_hash(&mut self, base_dir: &Path, algorithm: &str, maybe_tx: Option< Sender< Message > > ) -> anyhow::Result< () > {
+        if algorithm != "sha1" {
+            anyhow::bail!( "Calculating {} hashes is not supported", algorithm );
+        }
         let mut fullpath = PathBuf::new();
-        fullpath.push( &base_dir );
-        fullpath.push( &self.path() );
-        let mut f = match std::fs::File::open(&fullpath) {
-            Err( e ) => { dbg!( &fullpath ); panic!("") },   // :TODO: handle
+        fullpath.push( base_dir );
+        fullpath.push( self.path() );
+        let f = match std::fs::File::open(&fullpath) {
+            Err( e ) => anyhow::bail!( "Failed opening {:?}: {}", &fullpath, e ),
             Ok( f ) => f,
         };
 
-        let mut sha1 = Sha1::new();
-        let mut data = Vec::<u8>::new();
-        // :TODO: make configurable
-        const BLOCKSIZE: usize = 128*1024;
-
-        if BLOCKSIZE == 0 {
-            f.read_to_end(&mut data);
-            sha1.update(&mut data);
-        } else {
-            let mut r = BufReader::with_capacity( BLOCKSIZE, f );
-            let mut buffer = [0; BLOCKSIZE];
-            loop {
-                let n = r.read(&mut buffer)?;
-                if n == 0 {
-                    break;
-                }
-                sha1.update(&buffer[..n]);
-                if let Some( ref tx ) = maybe_tx {
-                    tx.send( Message::Progress( n ) );
-                }
-            }
+        if let Some( ref tx ) = maybe_tx {
+            let _ = tx.send( Message::FileStarted( self.path.clone(), self.size ) );
         }
-        let hash = sha1.digest();
-        self.set_hash( &hash.to_string().to_uppercase() );
+        let hash = hash_reader( f, maybe_tx.as_ref() )?;
+        self.set_hash( &hash );
         if let Some( ref tx ) = maybe_tx {
-            tx.send( Message::FileDone );
+            let _ = tx.send( Message::FileDone( self.path.clone(), self.size ) );
         }
         Ok(())
     }
 
+    pub fn set_mtime( &mut self, mtime: i64 ) {
+        self.mtime = Some( mtime );
+    }
+
+    pub fn set_path( &mut self, path: &Path ) {
+        self.path = path.to_owned();
+    }
+
     pub fn path( &self ) -> &PathBuf {
         &self.path
     }
@@ -83,51 +96,389 @@ impl ChecksumsEntry {
     pub fn hash( &self ) -> &str {
         &self.hash
     }
+
+    pub fn mtime( &self ) -> Option< i64 > {
+        self.mtime
+    }
+
+    pub fn has_hash( &self ) -> bool {
+        !self.hash.is_empty()
+    }
 }
 
-#[derive(Debug,Deserialize,Serialize)]
+// :TODO: make configurable
+const BLOCKSIZE: usize = 128*1024;
+
+/// Calculates the (uppercase hex) sha1 of everything readable from `r`.
+pub fn hash_reader< R: Read >( r: R, maybe_tx: Option< &Sender< Message > > ) -> anyhow::Result< String > {
+    let mut sha1 = Sha1::new();
+    let mut r = BufReader::with_capacity( BLOCKSIZE, r );
+    let mut buffer = vec![0; BLOCKSIZE];
+    loop {
+        let n = r.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        sha1.update(&buffer[..n]);
+        if let Some( tx ) = maybe_tx {
+            let _ = tx.send( Message::Progress( n ) );
+        }
+    }
+    Ok( sha1.digest().to_string().to_uppercase() )
+}
+
+/// The version of the manifest format written by `Checksums::save`.
+/// Manifests without a header are version 0.
+pub const FORMAT_VERSION: u64 = 1;
+
+/// Set for manifests from formats without sizes, all sizes are 0 then.
+pub const SIZES_OPTION: &str = "sizes";
+pub const SIZES_UNKNOWN: &str = "unknown";
+
+/// Describes how and where a manifest was produced.
+#[derive(Debug,Clone,Default,PartialEq,Deserialize,Serialize)]
+pub struct ManifestHeader {
+    format_version: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    tool_version: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    created: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    hostname: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    base_dir: String,
+    options: BTreeMap< String, String >,
+}
+
+impl ManifestHeader {
+    pub fn new() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            tool_version: env!( "CARGO_PKG_VERSION" ).to_string(),
+            created: chrono::Local::now().to_rfc3339_opts( chrono::SecondsFormat::Secs, false ),
+            hostname: hostname::get().map( |h| h.to_string_lossy().into_owned() ).unwrap_or_default(),
+            base_dir: String::new(),
+            options: BTreeMap::new(),
+        }
+    }
+
+    /// The header of manifests that did not bring one, version 0 until they are saved again.
+    pub fn unknown_origin() -> Self {
+        Self::default()
+    }
+
+    /// Only what describes the content, nothing that changes between identical runs.
+    pub fn canonical( &self ) -> Self {
+        Self {
+            format_version: self.format_version,
+            options: self.options.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn set_base_dir( &mut self, base_dir: &Path ) {
+        self.base_dir = base_dir.to_string_lossy().into_owned();
+    }
+
+    pub fn set_option( &mut self, name: &str, value: &str ) {
+        self.options.insert( name.to_string(), value.to_string() );
+    }
+
+    pub fn format_version( &self ) -> u64 {
+        self.format_version
+    }
+
+    pub fn options( &self ) -> &BTreeMap< String, String > {
+        &self.options
+    }
+
+    pub fn describe( &self ) -> String {
+        if self.created.is_empty() {
+            return "unknown origin".to_string();
+        }
+        format!( "created {} on {} from {} by version {}", self.created, self.hostname, self.base_dir, self.tool_version )
+    }
+}
+
+#[derive(Debug,Deserialize)]
 pub struct Checksums {
+    /// Missing in manifests of version 0.
+    #[serde(default)]
+    header: ManifestHeader,
     algorithm: String,
     entries: Vec<ChecksumsEntry>,
     total_size: u64,
+    #[serde(skip)]
+    path_matching: PathMatching,
+    /// Maps the keys of all paths to their entry, only used for non exact path matching.
+    #[serde(skip)]
+    index: HashMap< PathBuf, usize >,
+    /// Maps exact paths to their entry, built by the first lookup.
+    #[serde(skip)]
+    exact_index: OnceLock< HashMap< PathBuf, usize > >,
+    /// For binary manifests the entries stay in the file until they are needed.
+    #[serde(skip)]
+    binary: Option< BinaryManifest >,
+    #[serde(skip)]
+    decoded: OnceLock< Vec< ChecksumsEntry > >,
+    #[serde(skip)]
+    canonical: bool,
+}
+
+impl Serialize for Checksums {
+    fn serialize< S: Serializer >( &self, serializer: S ) -> Result< S::Ok, S::Error > {
+        #[derive(Serialize)]
+        struct Stored< 'a > {
+            header: ManifestHeader,
+            algorithm: &'a str,
+            entries: Vec< Cow< 'a, ChecksumsEntry > >,
+            total_size: u64,
+        }
+        let entries = self.saved_entries();
+        Stored {
+            header: self.saved_header(),
+            algorithm: &self.algorithm,
+            total_size: entries.iter().map( |e| e.size() ).sum(),
+            entries,
+        }.serialize( serializer )
+    }
 }
 
 impl Checksums {
     pub fn new( algorithm: &str ) -> Self {
         Self {
+            header: ManifestHeader::new(),
             algorithm: algorithm.to_string(),
             entries: Vec::new(),
             total_size: 0,
+            path_matching: PathMatching::default(),
+            index: HashMap::new(),
+            exact_index: OnceLock::new(),
+            binary: None,
+            decoded: OnceLock::new(),
+            canonical: false,
         }
     }
 
-    pub fn save( &self, filename: &str )-> Result<(), Box<dyn Error>> {
-        let json = serde_json::to_string( &self )?;
-        std::fs::write( filename, json )?;
+    /// Saves only what identifies the content, so identical trees give identical files.
+    pub fn set_canonical( &mut self, canonical: bool ) {
+        self.canonical = canonical;
+    }
+
+    pub fn is_canonical( &self ) -> bool {
+        self.canonical
+    }
+
+    /// The header as it is saved, see `set_canonical`, in the current format version.
+    pub fn saved_header( &self ) -> ManifestHeader {
+        let mut header = if self.canonical {
+            self.header.canonical()
+        } else {
+            self.header.clone()
+        };
+        header.format_version = FORMAT_VERSION;
+        header
+    }
+
+    /// The entries as they are saved, sorted by the raw bytes of their path
+    /// so the order does not depend on the file system.
+    pub fn saved_entries( &self ) -> Vec< Cow< '_, ChecksumsEntry > > {
+        let mut entries: Vec< &ChecksumsEntry > = self.entries().iter().collect();
+        entries.sort_by_cached_key( |e| portable_path::to_bytes( e.path() ) );
+        entries.into_iter().map( |e| {
+            if self.canonical && e.mtime.is_some() {
+                let mut e = e.clone();
+                e.mtime = None;
+                Cow::Owned( e )
+            } else {
+                Cow::Borrowed( e )
+            }
+        }).collect()
+    }
+
+    /// Uses a binary manifest in place, entries are only decoded when they are needed.
+    pub fn from_binary( binary: BinaryManifest ) -> anyhow::Result< Self > {
+        let ( header, algorithm ) = binary.meta()?;
+        let mut checksums = Self::new( &algorithm );
+        checksums.header = header;
+        checksums.total_size = binary.total_size();
+        checksums.binary = Some( binary );
+        Ok( checksums )
+    }
+
+    /// Moves the entries of a binary manifest into memory, before they are changed.
+    fn decode( &mut self ) {
+        if let Some( binary ) = self.binary.take() {
+            self.exact_index.take();
+            self.entries = self.decoded.take().unwrap_or_else( || binary.entries() );
+        }
+    }
+
+    /// Changes how `find` matches paths, and returns all groups of entries that now match the same path.
+    pub fn set_path_matching( &mut self, path_matching: PathMatching ) -> Vec< Vec< PathBuf > > {
+        if !path_matching.is_exact() {
+            self.decode();
+        }
+        self.path_matching = path_matching;
+        self.index.clear();
+        if path_matching.is_exact() {
+            return Vec::new();
+        }
+
+        let mut collisions: HashMap< PathBuf, Vec< PathBuf > > = HashMap::new();
+        for ( i, e ) in self.entries.iter().enumerate() {
+            let key = path_matching.key( &e.path );
+            if let Some( first ) = self.index.get( &key ) {
+                collisions.entry( key.clone() )
+                    .or_insert_with( || vec![ self.entries[ *first ].path.clone() ] )
+                    .push( e.path.clone() );
+            } else {
+                self.index.insert( key, i );
+            }
+        }
+        let mut collisions: Vec< Vec< PathBuf > > = collisions.into_values().collect();
+        collisions.sort();
+        collisions
+    }
+
+    /// Saves in the format and compression matching the extension of `filename`, e.g. `.csv.gz`.
+    pub fn save( &self, filename: &str ) -> anyhow::Result< () > {
+        self.save_with_format( filename, ManifestFormat::from_path( Path::new( filename ) ) )
+    }
+
+    pub fn save_with_format( &self, filename: &str, format: ManifestFormat ) -> anyhow::Result< () > {
+        self.save_with( filename, format, Compression::from_path( Path::new( filename ) ) )
+    }
+
+    pub fn save_with( &self, filename: &str, format: ManifestFormat, compression: Compression ) -> anyhow::Result< () > {
+        let w = BufWriter::new( std::fs::File::create( filename )? );
+        let mut w = compression.write_with( w, |w| format.writer().write( self, w ) )
+            .with_context( || format!( "Failed writing {}", filename ) )?;
+        w.flush()?;
         Ok(())
     }
 
+    /// Loads in the format detected from the extension and content of `filename`,
+    /// compressed files are detected by their content.
     pub fn load( filename: &str ) -> anyhow::Result< Checksums > {
-        let json = std::fs::read_to_string( &filename )?;
-        let s = serde_json::from_str( &json )?;
-        Ok(s)
+        if BinaryManifest::is_binary_file( Path::new( filename ) ) {
+            let binary = BinaryManifest::open( Path::new( filename ) )
+                .with_context( || format!( "Failed opening {}", filename ) )?;
+            return Self::from_binary( binary );
+        }
+        if sqlite_manifest::is_sqlite_file( Path::new( filename ) ) {
+            return sqlite_manifest::load( Path::new( filename ) )
+                .with_context( || format!( "Failed reading {}", filename ) );
+        }
+        let mut r = Self::open_decompressed( filename )?;
+        let format = ManifestFormat::detect( Path::new( filename ), r.fill_buf()? );
+        Self::load_from( filename, &mut r, format )
+    }
+
+    pub fn load_with_format( filename: &str, format: ManifestFormat ) -> anyhow::Result< Checksums > {
+        let mut r = Self::open_decompressed( filename )?;
+        Self::load_from( filename, &mut r, format )
+    }
+
+    /// Manifests can be larger than the memory, so they are decompressed while parsing.
+    fn open_decompressed( filename: &str ) -> anyhow::Result< BufReader< Box< dyn Read > > > {
+        let mut r = BufReader::new( std::fs::File::open( filename )? );
+        let compression = Compression::detect( r.fill_buf()? );
+        let decoder = compression.decoder( r )
+            .with_context( || format!( "Failed decompressing {}", filename ) )?;
+        Ok( BufReader::new( decoder ) )
+    }
+
+    fn load_from( filename: &str, r: &mut dyn Read, format: ManifestFormat ) -> anyhow::Result< Checksums > {
+        format.reader().read( r )
+            .with_context( || format!( "Failed reading {} as {:?}", filename, format ) )
+    }
+
+    pub fn header( &self ) -> &ManifestHeader {
+        &self.header
+    }
+
+    pub fn header_mut( &mut self ) -> &mut ManifestHeader {
+        &mut self.header
+    }
+
+    /// False for manifests from formats that do not record sizes.
+    pub fn has_sizes( &self ) -> bool {
+        self.header.options.get( SIZES_OPTION ).map( |s| s.as_str() ) != Some( SIZES_UNKNOWN )
+    }
+
+    /// Adds an entry, without hash, for every file below `base_dir`, skipping entries that can not be read.
+    pub fn add_dir( &mut self, base_dir: &Path ) -> anyhow::Result< () > {
+        self.header.set_base_dir( base_dir );
+        self.header.set_option( "symlinks", "skip" );
+        for e in WalkDir::new( base_dir ) {
+            match e {
+                Ok( e ) => match e.metadata() {
+                    Ok( m ) => if m.is_file() {
+                        let rp = e.path().strip_prefix( base_dir )?;
+                        self.add( ChecksumsEntry::from_metadata( rp, &m ) );
+                    },
+                    Err( e ) => eprintln!( "Skipping {:?}: {}", e.path(), e ),
+                },
+                Err( e ) => eprintln!( "Skipping {:?}: {}", e.path(), e ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Calculates the hashes of all entries in parallel.
+    pub fn calculate_hashes( &mut self, base_dir: &Path ) -> anyhow::Result< () > {
+        let algorithm = self.algorithm.clone();
+        self.par_iter_mut()
+            .filter( |e| !e.has_hash() )
+            .map( |e| e.calculate_hash( base_dir, &algorithm, None ) )
+            .collect()
     }
 
     pub fn add( &mut self, entry: ChecksumsEntry ) {
+        self.decode();
         self.total_size += entry.size;
+        if !self.path_matching.is_exact() {
+            let key = self.path_matching.key( &entry.path );
+            self.index.entry( key ).or_insert( self.entries.len() );
+        }
+        if let Some( index ) = self.exact_index.get_mut() {
+            index.entry( entry.path.clone() ).or_insert( self.entries.len() );
+        }
         self.entries.push( entry );
     }
 
-    pub fn find_mut( &mut self, filename: &PathBuf ) -> Option < &mut ChecksumsEntry > {
-        self.entries.iter_mut().find({ |e|
-            e.path == *filename
+    fn exact_index( &self ) -> &HashMap< PathBuf, usize > {
+        self.exact_index.get_or_init( || {
+            let mut index = HashMap::with_capacity( self.entries.len() );
+            for ( i, e ) in self.entries.iter().enumerate() {
+                index.entry( e.path.clone() ).or_insert( i );
+            }
+            index
         })
     }
 
-    pub fn find( &self, filename: &PathBuf ) -> Option < &ChecksumsEntry > {
-        self.entries.iter().find({ |e|
-            e.path == *filename
-        })
+    pub fn find( &self, filename: &Path ) -> Option < Cow< '_, ChecksumsEntry > > {
+        if !self.path_matching.is_exact() {
+            let i = *self.index.get( &self.path_matching.key( filename ) )?;
+            return self.entries.get( i ).map( Cow::Borrowed );
+        }
+        if let Some( binary ) = &self.binary {
+            return binary.find( filename ).map( Cow::Owned );
+        }
+        let i = *self.exact_index().get( filename )?;
+        self.entries.get( i ).map( Cow::Borrowed )
+    }
+
+    #[allow(dead_code)]
+    pub fn find_mut( &mut self, filename: &Path ) -> Option < &mut ChecksumsEntry > {
+        self.decode();
+        let i = if self.path_matching.is_exact() {
+            *self.exact_index().get( filename )?
+        } else {
+            *self.index.get( &self.path_matching.key( filename ) )?
+        };
+        self.entries.get_mut( i )
     }
 
     pub fn algorithm( &self ) -> &str {
@@ -135,25 +486,43 @@ impl Checksums {
     }
 
     pub fn len( &self ) -> usize {
-        self.entries.len()
+        match &self.binary {
+            Some( binary ) => binary.len(),
+            None => self.entries.len(),
+        }
     }
 
     pub fn total_size( &self ) -> u64 {
         self.total_size
     }
 
-    pub fn iter_mut( &mut self ) -> std::slice::IterMut::< ChecksumsEntry > {
+    #[allow(dead_code)]
+    pub fn iter_mut( &mut self ) -> std::slice::IterMut::< '_, ChecksumsEntry > {
+        self.decode();
+        self.exact_index.take();
         self.entries.iter_mut()
     }
 
-    pub fn par_iter_mut( &mut self ) -> rayon::slice::IterMut::< ChecksumsEntry > {
+    pub fn par_iter_mut( &mut self ) -> rayon::slice::IterMut::< '_, ChecksumsEntry > {
+        self.decode();
+        self.exact_index.take();
         self.entries.par_iter_mut()
     }
 
     pub fn entries_mut( &mut self ) -> &mut Vec<ChecksumsEntry> {
+        self.decode();
+        self.exact_index.take();
         &mut self.entries
     }
+    /// All entries by their exact path, for looking up many paths at once.
+    pub fn by_path( &self ) -> HashMap< &Path, &ChecksumsEntry > {
+        self.entries().iter().map( |e| ( e.path.as_path(), e ) ).collect()
+    }
+
     pub fn entries( &self ) -> &Vec<ChecksumsEntry> {
-        &self.entries
+        match &self.binary {
+            Some( binary ) => self.decoded.get_or_init( || binary.entries() ),
+            None => &self.entries,
+        }
     }
 }
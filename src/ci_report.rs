@@ -0,0 +1,146 @@
+use std::fmt::Write;
+use std::path::{Path,PathBuf};
+use serde_json::json;
+use crate::portable_path;
+use crate::summary::{Outcome,Summary};
+
+/// The outcomes of files that are in the manifest, added files are only reported on request.
+const EXPECTED: &[Outcome] = &[ Outcome::Unchanged, Outcome::Changed, Outcome::Removed, Outcome::Corrupted, Outcome::Errored ];
+
+fn xml_escape( s: &str ) -> String {
+    let mut escaped = String::with_capacity( s.len() );
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str( "&amp;" ),
+            '<' => escaped.push_str( "&lt;" ),
+            '>' => escaped.push_str( "&gt;" ),
+            '"' => escaped.push_str( "&quot;" ),
+            '\'' => escaped.push_str( "&apos;" ),
+            // not allowed in XML 1.0, not even as a character reference
+            c if ( c as u32 ) < 0x20 && !matches!( c, '\t' | '\n' | '\r' ) => escaped.push( char::REPLACEMENT_CHARACTER ),
+            c => escaped.push( c ),
+        }
+    }
+    escaped
+}
+
+/// Percent encodes everything but unreserved characters and `/`, see RFC 3986.
+fn uri_escape( bytes: &[u8] ) -> String {
+    let mut escaped = String::with_capacity( bytes.len() );
+    for b in bytes {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => escaped.push( *b as char ),
+            b => { let _ = write!( escaped, "%{:02X}", b ); },
+        }
+    }
+    escaped
+}
+
+fn description( outcome: Outcome ) -> &'static str {
+    match outcome {
+        Outcome::Unchanged => "File matches the manifest",
+        Outcome::Changed => "File differs from the manifest",
+        Outcome::Added => "File is not in the manifest",
+        Outcome::Removed => "File from the manifest is missing",
+        Outcome::Corrupted => "File content changed without its modification time, it might be corrupted",
+        Outcome::Hashed => "File was hashed",
+        Outcome::Errored => "File could not be read",
+    }
+}
+
+/// All reported files, sorted by path.
+fn reported( summary: &Summary, fail_on_added: bool ) -> Vec< ( Outcome, &PathBuf ) > {
+    let mut outcomes = EXPECTED.to_vec();
+    if fail_on_added {
+        outcomes.push( Outcome::Added );
+    }
+    let mut files: Vec< ( Outcome, &PathBuf ) > = outcomes.iter()
+        .flat_map( |o| summary.files( *o ).iter().map( move |( p, _ )| ( *o, p ) ) )
+        .collect();
+    files.sort_by( |a, b| a.1.cmp( b.1 ) );
+    files
+}
+
+/// One test case per file, failures for changed, removed, corrupted and, with `fail_on_added`, added files.
+pub fn write_junit( filename: &str, suite: &str, summary: &Summary, fail_on_added: bool ) -> anyhow::Result< () > {
+    let files = reported( summary, fail_on_added );
+    let errors = files.iter().filter( |( o, _ )| *o == Outcome::Errored ).count();
+    let failures = files.iter().filter( |( o, _ )| !matches!( o, Outcome::Unchanged | Outcome::Errored ) ).count();
+    let suite = xml_escape( suite );
+
+    let mut xml = String::new();
+    writeln!( xml, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>" )?;
+    writeln!( xml, "<testsuites name=\"folder-compare-rs\" tests=\"{}\" failures=\"{}\" errors=\"{}\">", files.len(), failures, errors )?;
+    writeln!( xml, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">", suite, files.len(), failures, errors )?;
+    for ( outcome, path ) in files {
+        let name = xml_escape( &portable_path::encode( path ) );
+        match outcome {
+            Outcome::Unchanged => writeln!( xml, "    <testcase classname=\"verify\" name=\"{}\"/>", name )?,
+            Outcome::Errored => writeln!( xml, "    <testcase classname=\"verify\" name=\"{}\"><error type=\"{}\" message=\"{}\"/></testcase>",
+                name, outcome.name(), description( outcome ) )?,
+            _ => writeln!( xml, "    <testcase classname=\"verify\" name=\"{}\"><failure type=\"{}\" message=\"{}\"/></testcase>",
+                name, outcome.name(), description( outcome ) )?,
+        }
+    }
+    writeln!( xml, "  </testsuite>" )?;
+    writeln!( xml, "</testsuites>" )?;
+    std::fs::write( filename, xml )?;
+    Ok(())
+}
+
+/// A SARIF 2.1.0 log with one result per mismatch, located relative to `base_dir`.
+pub fn write_sarif( filename: &str, base_dir: &Path, summary: &Summary, fail_on_added: bool ) -> anyhow::Result< () > {
+    let rules = [ Outcome::Changed, Outcome::Removed, Outcome::Added, Outcome::Corrupted, Outcome::Errored ];
+    let results: Vec< serde_json::Value > = rules.iter()
+        .flat_map( |o| summary.files( *o ).iter().map( move |( p, _ )| ( *o, p ) ) )
+        .map( |( outcome, path )| {
+            let level = if outcome == Outcome::Added && !fail_on_added { "note" } else { "error" };
+            json!({
+                "ruleId": outcome.name(),
+                "level": level,
+                "message": { "text": format!( "{}: {}", description( outcome ), portable_path::encode( path ) ) },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": {
+                            "uri": uri_escape( &portable_path::to_bytes( path ) ),
+                            "uriBaseId": "BASEDIR",
+                        }
+                    }
+                }],
+            })
+        })
+        .collect();
+
+    let mut base_uri = uri_escape( base_dir.to_string_lossy().replace( '\\', "/" ).as_bytes() );
+    if !base_uri.starts_with( '/' ) {
+        base_uri.insert( 0, '/' );
+    }
+    if !base_uri.ends_with( '/' ) {
+        base_uri.push( '/' );
+    }
+    let sarif = json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "folder-compare-rs",
+                    "version": env!( "CARGO_PKG_VERSION" ),
+                    "informationUri": env!( "CARGO_PKG_HOMEPAGE" ),
+                    "rules": rules.iter().map( |o| json!({
+                        "id": o.name(),
+                        "shortDescription": { "text": description( *o ) },
+                    })).collect::< Vec< _ > >(),
+                }
+            },
+            "originalUriBaseIds": {
+                "BASEDIR": { "uri": format!( "file://{}", base_uri ) },
+            },
+            "results": results,
+        }],
+    });
+    let mut f = std::fs::File::create( filename )?;
+    serde_json::to_writer_pretty( &mut f, &sarif )?;
+    std::io::Write::write_all( &mut f, b"\n" )?;
+    Ok(())
+}
@@ -1,17 +1,80 @@
-use std::error::Error;
-use walkdir::WalkDir;
-use std::{fmt,fs};
-use std::path::{Path,PathBuf};
-use std::io::{Read,Write};
+use std::path::PathBuf;
+use std::io::Write;
+use crate::archive::load_checksums;
 use crate::checksums::*;
 use crate::command_async::CommandAsync;
-use indicatif::{ProgressBar,ProgressStyle};
-use sha1::Sha1;
-
-use rayon::prelude::*;
+use crate::html_report::HtmlReport;
+use crate::path_matching::PathMatching;
+use crate::portable_path;
+use crate::message::Message;
+use crate::progress::{step_bar,Progress,ProgressMode};
+use crate::summary::{Outcome,Summary};
+use indicatif::ProgressBar;
 
 use async_trait::async_trait;
-use std::collections::BTreeSet;
+
+/// The differences between two sets of checksums.
+#[derive(Debug,Default)]
+pub struct ChangeSet {
+    pub added: Vec< PathBuf >,
+    pub changed: Vec< PathBuf >,
+    pub removed: Vec< PathBuf >,
+    pub unchanged: Vec< PathBuf >,
+}
+
+impl ChangeSet {
+    pub fn new( old_checksums: &Checksums, new_checksums: &Checksums, bar: Option< &ProgressBar > ) -> Self {
+        // :TODO: there is high potential for doing this smarter, but for now we just do the brute force, straight forward things
+        let mut change_set = ChangeSet::default();
+        let compare_sizes = old_checksums.has_sizes() && new_checksums.has_sizes();
+
+        for o in old_checksums.entries() {
+            if let Some( bar ) = bar { bar.inc( 1 ) };
+            match new_checksums.find( o.path() ) {
+                None => {
+                    change_set.removed.push( o.path().to_owned() );
+                },
+                Some( n ) => {
+                    if ( compare_sizes && o.size() != n.size() ) || o.hash() != n.hash() {
+                        change_set.changed.push( o.path().to_owned() );
+                    } else {
+                        change_set.unchanged.push( o.path().to_owned() );
+                    }
+                }
+            }
+        }
+
+        for n in new_checksums.entries() {
+            if let Some( bar ) = bar { bar.inc( 1 ) };
+            if old_checksums.find( n.path() ).is_none() {
+                change_set.added.push( n.path().to_owned() );
+            }
+        }
+
+        change_set
+    }
+
+    pub fn is_clean( &self ) -> bool {
+        self.added.is_empty() && self.changed.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Switches `checksums` to `path_matching`, reporting entries that can no longer be told apart.
+pub fn apply_path_matching( name: &str, checksums: &mut Checksums, path_matching: PathMatching ) {
+    for collision in checksums.set_path_matching( path_matching ) {
+        let paths: Vec< String > = collision.iter().map( |p| p.to_string_lossy().into_owned() ).collect();
+        println!( "WARNING: Paths in {} collide, only the first one is compared: {}", name, paths.join( ", " ) );
+    }
+}
+
+pub fn write_path_list( filename: &str, paths: &[PathBuf] ) -> anyhow::Result< () > {
+    let mut f = std::fs::File::create( filename )?;
+    for e in paths {
+        f.write_all( &portable_path::to_bytes( e ) )?;
+        f.write_all( b"\n" )?;
+    }
+    Ok(())
+}
 
 #[derive(Debug)]
 pub struct Compare {
@@ -20,6 +83,11 @@ pub struct Compare {
     changed_file: Option< String >,
     added_file: Option< String >,
     removed_file: Option< String >,
+    path_matching: PathMatching,
+    progress: ProgressMode,
+    progress_fd: Option< i32 >,
+    verbose: bool,
+    html_report: Option< String >,
 }
 
 impl Compare {
@@ -30,6 +98,11 @@ impl Compare {
             changed_file: None,
             added_file: None,
             removed_file: None,
+            path_matching: PathMatching::default(),
+            progress: ProgressMode::Bars,
+            progress_fd: None,
+            verbose: false,
+            html_report: None,
         }
     }
 
@@ -42,83 +115,96 @@ impl Compare {
     pub fn set_added_file( &mut self, added_file: &str ) {
         self.added_file = Some( added_file.to_string() )
     }
+    pub fn set_path_matching( &mut self, path_matching: PathMatching ) {
+        self.path_matching = path_matching;
+    }
+    pub fn set_progress( &mut self, progress: ProgressMode ) {
+        self.progress = progress;
+    }
+    pub fn set_progress_fd( &mut self, progress_fd: i32 ) {
+        self.progress_fd = Some( progress_fd );
+    }
+    pub fn set_verbose( &mut self, verbose: bool ) {
+        self.verbose = verbose;
+    }
+    pub fn set_html_report( &mut self, html_report: &str ) {
+        self.html_report = Some( html_report.to_string() )
+    }
 }
 
 #[async_trait]
 impl CommandAsync for Compare {
     async fn run( &mut self ) -> anyhow::Result<()> {
-        let old_checksums = Checksums::load( &self.checksum_file_old )?;
-        let new_checksums = Checksums::load( &self.checksum_file_new )?;
+        let mut summary = Summary::new( &[ Outcome::Unchanged, Outcome::Changed, Outcome::Added, Outcome::Removed ] );
+        let mut old_checksums = load_checksums( &self.checksum_file_old )?;
+        let mut new_checksums = load_checksums( &self.checksum_file_new )?;
+        apply_path_matching( &self.checksum_file_old, &mut old_checksums, self.path_matching );
+        apply_path_matching( &self.checksum_file_new, &mut new_checksums, self.path_matching );
 
 //        dbg!(&old_checksums, &new_checksums);
 
-        let bar = ProgressBar::new( 1_000_000u64 );
-        let spinner_style = ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .template("{prefix:.bold.dim} {spinner} {wide_msg} {pos} files found.");
-
-        bar.set_style(spinner_style.clone());
-
-        // :TODO: there is high potential for doing this smarter, but for now we just do the brute force, straight forward things
-        let mut added = Vec::new();
-        let mut changed = Vec::new();
-        let mut removed = Vec::new();
-        let mut unchanged = Vec::new();
-
         if old_checksums.algorithm() != new_checksums.algorithm() {
             println!("ERROR: Different algorithms for checksums");
             return Ok(());
         };
-        for o in old_checksums.entries() {
-            bar.inc( 1 );
-            match new_checksums.find( &o.path() ) {
-                None => {
-                    removed.push( o.path().to_owned() );
-                },
-                Some( n ) => {
-                    if o.size() != n.size() {
-                        changed.push( o.path().to_owned() );
-                    } else {
-                        if o.hash() != n.hash() {
-                            changed.push( o.path().to_owned() );
-                        } else {
-                            unchanged.push( o.path().to_owned() );
-                        }
-                    }
-                }
+
+        // manifests from before the header was introduced do not know their options
+        let both_known = !old_checksums.header().options().is_empty() && !new_checksums.header().options().is_empty();
+        if both_known && old_checksums.header().options() != new_checksums.header().options() {
+            println!( "WARNING: Checksums were created with different options" );
+            for ( name, checksums ) in [ ( &self.checksum_file_old, &old_checksums ), ( &self.checksum_file_new, &new_checksums ) ].iter() {
+                println!( "    {} {}, options {:?}", name, checksums.header().describe(), checksums.header().options() );
             }
         }
 
-        for n in new_checksums.entries() {
-            bar.inc( 1 );
-            match old_checksums.find( &n.path() ) {
-                Some( _ ) => {},
-                None => added.push( n.path().to_owned() ),
+        // nothing is hashed, json consumers still learn when comparing starts and ends
+        let json = if self.progress == ProgressMode::Json {
+            let ( tx, progress ) = Progress::new( self.progress, self.progress_fd, "Comparing", 1 ).spawn();
+            tx.send( Message::Started( 0, 0 ) )?;
+            Some( ( tx, progress ) )
+        } else {
+            None
+        };
+        let bar = step_bar( self.progress, "Comparing", ( old_checksums.len() + new_checksums.len() ) as u64 );
+        let change_set = ChangeSet::new( &old_checksums, &new_checksums, Some( &bar ) );
+        bar.finish();
+        if let Some( ( tx, progress ) ) = json {
+            tx.send( Message::Done )?;
+            let _ = progress.join();
+        }
+
+        let mut report = self.html_report.as_ref().map( |_| HtmlReport::new( &format!( "Comparing {} to {}", self.checksum_file_old, self.checksum_file_new ) ) );
+        for ( outcome, paths ) in [
+            ( Outcome::Unchanged, &change_set.unchanged ),
+            ( Outcome::Changed, &change_set.changed ),
+            ( Outcome::Added, &change_set.added ),
+            ( Outcome::Removed, &change_set.removed ),
+        ].iter() {
+            for p in paths.iter() {
+                let o = old_checksums.find( p );
+                let n = new_checksums.find( p );
+                let size = n.as_ref().or( o.as_ref() ).map_or( 0, |e| e.size() );
+                summary.add( *outcome, p, size );
+                if let Some( report ) = &mut report {
+                    report.add( *outcome, p, o.as_deref(), n.as_deref() );
+                }
             }
         }
+        summary.print( self.verbose );
+        if let ( Some( report ), Some( html_report ) ) = ( &report, &self.html_report ) {
+            report.save( html_report )?;
+        }
 /*
-        dbg!(&unchanged);
-        dbg!(&changed);
-        dbg!(&removed);
-        dbg!(&added);
+        dbg!(&change_set);
 */
         if let Some( changed_file ) = &self.changed_file {
-            let mut f = std::fs::File::create(&changed_file).expect("create file failed for changed file");
-            for e in changed {
-                f.write_all(format!("{}\n", e.to_string_lossy() ).as_bytes());
-            }
+            write_path_list( changed_file, &change_set.changed )?;
         }
         if let Some( removed_file ) = &self.removed_file {
-            let mut f = std::fs::File::create(&removed_file).expect("create file failed for removed file");
-            for e in removed {
-                f.write_all(format!("{}\n", e.to_string_lossy() ).as_bytes());
-            }
+            write_path_list( removed_file, &change_set.removed )?;
         }
         if let Some( added_file ) = &self.added_file {
-            let mut f = std::fs::File::create(&added_file).expect("create file failed for added file");
-            for e in added {
-                f.write_all(format!("{}\n", e.to_string_lossy() ).as_bytes());
-            }
+            write_path_list( added_file, &change_set.added )?;
         }
         Ok(())
     }
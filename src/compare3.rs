@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap,BTreeSet,HashMap};
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use crate::archive::load_checksums;
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::portable_path;
+
+use async_trait::async_trait;
+
+/// How a path changed in two derived trees compared to their common base.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Classification {
+    Unchanged,
+    ChangedOnlyOurs,
+    ChangedOnlyTheirs,
+    ChangedBothIdentically,
+    Conflict,
+}
+
+impl Classification {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Classification::Unchanged => "unchanged",
+            Classification::ChangedOnlyOurs => "changed-only-ours",
+            Classification::ChangedOnlyTheirs => "changed-only-theirs",
+            Classification::ChangedBothIdentically => "changed-both-identically",
+            Classification::Conflict => "conflict",
+        }
+    }
+}
+
+/// The content of a path in one tree, `None` if it does not exist there.
+fn version( checksums: &HashMap< &Path, &ChecksumsEntry >, path: &Path, compare_sizes: bool ) -> Option< ( Option< u64 >, String ) > {
+    checksums.get( path ).map( |e| ( Some( e.size() ).filter( |_| compare_sizes ), e.hash().to_string() ) )
+}
+
+pub fn classify( base: &Checksums, ours: &Checksums, theirs: &Checksums ) -> BTreeMap< PathBuf, Classification > {
+    let paths: BTreeSet< &PathBuf > = base.entries().iter()
+        .chain( ours.entries().iter() )
+        .chain( theirs.entries().iter() )
+        .map( |e| e.path() )
+        .collect();
+    // manifests without sizes load them as 0
+    let compare_sizes = base.has_sizes() && ours.has_sizes() && theirs.has_sizes();
+    let ( base, ours, theirs ) = ( base.by_path(), ours.by_path(), theirs.by_path() );
+
+    paths.into_iter().map( |p| {
+        let b = version( &base, p, compare_sizes );
+        let o = version( &ours, p, compare_sizes );
+        let t = version( &theirs, p, compare_sizes );
+        let classification = match ( o == b, t == b ) {
+            ( true, true ) => Classification::Unchanged,
+            ( false, true ) => Classification::ChangedOnlyOurs,
+            ( true, false ) => Classification::ChangedOnlyTheirs,
+            ( false, false ) if o == t => Classification::ChangedBothIdentically,
+            ( false, false ) => Classification::Conflict,
+        };
+        ( p.to_owned(), classification )
+    }).collect()
+}
+
+#[derive(Debug)]
+pub struct Compare3 {
+    checksum_file_base: String,
+    checksum_file_ours: String,
+    checksum_file_theirs: String,
+    report_file: Option< String >,
+}
+
+impl Compare3 {
+    pub fn new( checksum_file_base: &str, checksum_file_ours: &str, checksum_file_theirs: &str ) -> Self {
+        Self {
+            checksum_file_base: checksum_file_base.to_string(),
+            checksum_file_ours: checksum_file_ours.to_string(),
+            checksum_file_theirs: checksum_file_theirs.to_string(),
+            report_file: None,
+        }
+    }
+
+    pub fn set_report_file( &mut self, report_file: &str ) {
+        self.report_file = Some( report_file.to_string() )
+    }
+}
+
+#[async_trait]
+impl CommandAsync for Compare3 {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let base = load_checksums( &self.checksum_file_base )?;
+        let ours = load_checksums( &self.checksum_file_ours )?;
+        let theirs = load_checksums( &self.checksum_file_theirs )?;
+
+        if base.algorithm() != ours.algorithm() || base.algorithm() != theirs.algorithm() {
+            println!("ERROR: Different algorithms for checksums");
+            return Ok(());
+        };
+
+        let classifications = classify( &base, &ours, &theirs );
+
+        let mut counts: BTreeMap< Classification, usize > = BTreeMap::new();
+        for c in classifications.values() {
+            *counts.entry( *c ).or_default() += 1;
+        }
+        for ( c, n ) in counts.iter() {
+            println!( "{:>8} {}", n, c.name() );
+        }
+
+        if let Some( report_file ) = &self.report_file {
+            let mut f = std::fs::File::create( report_file )?;
+            for ( p, c ) in classifications.iter() {
+                f.write_all( format!( "{}\t", c.name() ).as_bytes() )?;
+                f.write_all( &portable_path::to_bytes( p ) )?;
+                f.write_all( b"\n" )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest( entries: &[ ( &str, u64, &str ) ] ) -> Checksums {
+        let mut checksums = Checksums::new( "sha1" );
+        for ( path, size, hash ) in entries {
+            checksums.add( ChecksumsEntry::new( Path::new( path ), *size, hash ) );
+        }
+        checksums
+    }
+
+    #[test]
+    fn classify_ignores_sizes_unless_all_have_them() {
+        let base = manifest( &[ ( "a", 1, "AA" ), ( "b", 1, "BB" ), ( "c", 1, "CC" ) ] );
+        let ours = manifest( &[ ( "a", 1, "AA" ), ( "b", 1, "B2" ), ( "c", 1, "C2" ) ] );
+        let mut theirs = manifest( &[ ( "a", 0, "AA" ), ( "b", 0, "BB" ), ( "c", 0, "C3" ) ] );
+        theirs.header_mut().set_option( SIZES_OPTION, SIZES_UNKNOWN );
+
+        let classified = classify( &base, &ours, &theirs );
+        assert_eq!( classified[ Path::new( "a" ) ], Classification::Unchanged );
+        assert_eq!( classified[ Path::new( "b" ) ], Classification::ChangedOnlyOurs );
+        assert_eq!( classified[ Path::new( "c" ) ], Classification::Conflict );
+
+        // with sizes everywhere a different size is a change
+        let theirs = manifest( &[ ( "a", 2, "AA" ) ] );
+        assert_eq!( classify( &base, &base, &theirs )[ Path::new( "a" ) ], Classification::ChangedOnlyTheirs );
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{BTreeSet,HashMap};
+use std::io::Write;
+use std::path::{Path,PathBuf};
+use crate::archive::load_checksums;
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::portable_path;
+
+use async_trait::async_trait;
+
+/// The content of a path in one replica, `None` if it does not exist there.
+/// The size is only known when every replica has sizes.
+type Version = Option< ( Option< u64 >, String ) >;
+
+/// Which replicas agree on a single path.
+#[derive(Debug)]
+pub struct PathAgreement {
+    pub path: PathBuf,
+    /// The distinct versions, most common first, so the first one is the majority, if there is one.
+    pub versions: Vec< Version >,
+    /// False when the most common versions are tied.
+    pub has_majority: bool,
+    /// For every replica the index into `versions`.
+    pub replicas: Vec< usize >,
+}
+
+impl PathAgreement {
+    pub fn is_unanimous( &self ) -> bool {
+        self.versions.len() == 1
+    }
+
+    pub fn outliers( &self ) -> impl Iterator< Item = usize > + '_ {
+        self.replicas.iter().enumerate().filter( |( _, v )| **v != 0 ).map( |( r, _ )| r )
+    }
+}
+
+pub fn agreement_matrix( replicas: &[Checksums] ) -> Vec< PathAgreement > {
+    let paths: BTreeSet< &PathBuf > = replicas.iter()
+        .flat_map( |c| c.entries().iter().map( |e| e.path() ) )
+        .collect();
+
+    // manifests without sizes load them as 0
+    let compare_sizes = replicas.iter().all( |c| c.has_sizes() );
+    let indices: Vec< HashMap< &Path, &ChecksumsEntry > > = replicas.iter().map( |c| c.by_path() ).collect();
+
+    paths.into_iter().map( |p| {
+        let found: Vec< Version > = indices.iter()
+            .map( |c| c.get( p.as_path() ).map( |e| ( Some( e.size() ).filter( |_| compare_sizes ), e.hash().to_string() ) ) )
+            .collect();
+
+        // count the distinct versions, keeping the order in which they were seen for ties
+        let mut counts: Vec< ( Version, usize ) > = Vec::new();
+        for v in found.iter() {
+            match counts.iter_mut().find( |( c, _ )| c == v ) {
+                Some( ( _, n ) ) => *n += 1,
+                None => counts.push( ( v.clone(), 1 ) ),
+            }
+        }
+        counts.sort_by_key( |( _, n )| std::cmp::Reverse( *n ) );
+        let has_majority = counts.len() < 2 || counts[ 0 ].1 > counts[ 1 ].1;
+        let versions: Vec< Version > = counts.into_iter().map( |( v, _ )| v ).collect();
+        let replicas = found.iter()
+            .map( |f| versions.iter().position( |v| v == f ).unwrap_or_default() )
+            .collect();
+
+        PathAgreement {
+            path: p.to_owned(),
+            versions,
+            has_majority,
+            replicas,
+        }
+    }).collect()
+}
+
+fn version_label( index: usize ) -> String {
+    if index < 26 {
+        ( ( b'A' + index as u8 ) as char ).to_string()
+    } else {
+        format!( "V{}", index )
+    }
+}
+
+fn describe( version: &Version ) -> String {
+    match version {
+        Some( ( Some( size ), hash ) ) => format!( "{} ({} bytes)", hash, size ),
+        Some( ( None, hash ) ) => hash.clone(),
+        None => "missing".to_string(),
+    }
+}
+
+#[derive(Debug)]
+pub struct CompareMany {
+    checksum_files: Vec< String >,
+    report_file: Option< String >,
+}
+
+impl CompareMany {
+    pub fn new( checksum_files: &[String] ) -> Self {
+        Self {
+            checksum_files: checksum_files.to_vec(),
+            report_file: None,
+        }
+    }
+
+    pub fn set_report_file( &mut self, report_file: &str ) {
+        self.report_file = Some( report_file.to_string() )
+    }
+}
+
+#[async_trait]
+impl CommandAsync for CompareMany {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let mut replicas = Vec::new();
+        for f in self.checksum_files.iter() {
+            replicas.push( load_checksums( f )? );
+        }
+        if replicas.iter().any( |r| r.algorithm() != replicas[ 0 ].algorithm() ) {
+            println!("ERROR: Different algorithms for checksums");
+            return Ok(());
+        };
+
+        let matrix = agreement_matrix( &replicas );
+
+        let mut outlier_counts = vec![ 0; replicas.len() ];
+        for a in matrix.iter().filter( |a| !a.is_unanimous() ) {
+            println!( "{}", a.path.to_string_lossy() );
+            if !a.has_majority {
+                // without a majority nobody can be called an outlier
+                println!( "    no majority" );
+                for ( f, v ) in self.checksum_files.iter().zip( a.replicas.iter() ) {
+                    println!( "    replica  {} {}", f, describe( &a.versions[ *v ] ) );
+                }
+                continue;
+            }
+            println!( "    majority {}", describe( &a.versions[ 0 ] ) );
+            for r in a.outliers() {
+                outlier_counts[ r ] += 1;
+                println!( "    outlier  {} {}", self.checksum_files[ r ], describe( &a.versions[ a.replicas[ r ] ] ) );
+            }
+        }
+
+        let disagreeing = matrix.iter().filter( |a| !a.is_unanimous() ).count();
+        println!( "{} paths in {} replicas. {} paths agree, {} do not.", matrix.len(), replicas.len(), matrix.len() - disagreeing, disagreeing );
+        for ( f, n ) in self.checksum_files.iter().zip( outlier_counts.iter() ) {
+            println!( "{:>8} outliers in {}", n, f );
+        }
+
+        if let Some( report_file ) = &self.report_file {
+            let mut f = std::fs::File::create( report_file )?;
+            f.write_all( format!( "path\t{}\n", self.checksum_files.join( "\t" ) ).as_bytes() )?;
+            for a in matrix.iter() {
+                let labels: Vec< String > = a.replicas.iter()
+                    .map( |v| if a.versions[ *v ].is_some() { version_label( *v ) } else { "-".to_string() } )
+                    .collect();
+                f.write_all( &portable_path::to_bytes( &a.path ) )?;
+                f.write_all( format!( "\t{}\n", labels.join( "\t" ) ).as_bytes() )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest( entries: &[ ( &str, u64, &str ) ] ) -> Checksums {
+        let mut checksums = Checksums::new( "sha1" );
+        for ( path, size, hash ) in entries {
+            checksums.add( ChecksumsEntry::new( Path::new( path ), *size, hash ) );
+        }
+        checksums
+    }
+
+    #[test]
+    fn agreement_ignores_sizes_unless_all_have_them() {
+        let sized = || manifest( &[ ( "a", 1, "AA" ), ( "b", 1, "BB" ) ] );
+        let mut sum_file = manifest( &[ ( "a", 0, "AA" ), ( "b", 0, "B2" ) ] );
+        sum_file.header_mut().set_option( SIZES_OPTION, SIZES_UNKNOWN );
+
+        let matrix = agreement_matrix( &[ sized(), sized(), sum_file ] );
+        assert!( matrix[ 0 ].is_unanimous() );
+        assert_eq!( matrix[ 0 ].versions, vec![ Some( ( None, "AA".to_string() ) ) ] );
+        assert!( matrix[ 1 ].has_majority );
+        assert_eq!( matrix[ 1 ].outliers().collect::< Vec< usize > >(), vec![ 2 ] );
+
+        let resized = manifest( &[ ( "a", 2, "AA" ), ( "b", 1, "BB" ) ] );
+        let matrix = agreement_matrix( &[ sized(), sized(), resized ] );
+        assert_eq!( matrix[ 0 ].outliers().collect::< Vec< usize > >(), vec![ 2 ] );
+    }
+}
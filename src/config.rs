@@ -0,0 +1,166 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap,BTreeSet};
+use std::path::{Path,PathBuf};
+use clap::ArgMatches;
+use serde::Deserialize;
+
+/// The file with the named profiles, bundling the options of jobs that are run over and over.
+///
+/// ```toml
+/// [profiles.assets]
+/// base-dir = "build/assets"
+/// checksum-file = "assets.json"
+/// exclude = [ "*.tmp", "cache/*" ]
+/// junit = "assets-junit.xml"
+/// ```
+///
+/// The keys are the long names of the command line options, which always win over the profile.
+/// Flags are set with `true`, a flag or value of the profile is dropped with `--unset <option>`.
+/// Relative paths are used as they are, just like on the command line.
+pub const CONFIG_FILE: &str = "folder-compare.toml";
+
+#[derive(Debug,Default,Deserialize)]
+struct Config {
+    #[serde(default)]
+    profiles: BTreeMap< String, BTreeMap< String, toml::Value > >,
+}
+
+/// The values of one profile by option name, flags that are set have no values.
+#[derive(Debug,Default)]
+pub struct Profile {
+    name: String,
+    values: BTreeMap< String, Vec< String > >,
+}
+
+impl Profile {
+    fn from_table( name: &str, table: &BTreeMap< String, toml::Value > ) -> anyhow::Result< Self > {
+        let mut values = BTreeMap::new();
+        for ( key, value ) in table {
+            let value = match value {
+                toml::Value::Boolean( false ) => continue,
+                toml::Value::Boolean( true ) => Vec::new(),
+                toml::Value::String( s ) => vec![ s.clone() ],
+                toml::Value::Integer( i ) => vec![ i.to_string() ],
+                toml::Value::Array( a ) => a.iter().map( |v| match v {
+                    toml::Value::String( s ) => Ok( s.clone() ),
+                    toml::Value::Integer( i ) => Ok( i.to_string() ),
+                    v => anyhow::bail!( "Profile {}: unsupported value {} in {}", name, v, key ),
+                }).collect::< anyhow::Result< Vec< String > > >()?,
+                v => anyhow::bail!( "Profile {}: unsupported value {} for {}", name, v, key ),
+            };
+            values.insert( key.clone(), value );
+        }
+        Ok( Self {
+            name: name.to_string(),
+            values,
+        })
+    }
+}
+
+/// The config files in the order they are searched.
+fn config_files( base_dir: &Path ) -> Vec< PathBuf > {
+    let mut files = vec![ base_dir.join( CONFIG_FILE ) ];
+    if let Some( config_dir ) = dirs::config_dir() {
+        files.push( config_dir.join( "folder-compare" ).join( CONFIG_FILE ) );
+    }
+    files
+}
+
+/// Loads the profile selected with `--profile`, from the first config file that has it.
+pub fn load_profile( sub_matches: &ArgMatches ) -> anyhow::Result< Option< Profile > > {
+    let name = match sub_matches.value_of( "profile" ) {
+        Some( name ) => name,
+        None => return Ok( None ),
+    };
+    let base_dir = Path::new( sub_matches.value_of( "base-dir" ).unwrap_or( "." ) );
+    let files = config_files( base_dir );
+    for file in files.iter().filter( |f| f.is_file() ) {
+        let config: Config = toml::from_str( &std::fs::read_to_string( file )? )
+            .map_err( |e| anyhow::anyhow!( "{:?}: {}", file, e ) )?;
+        if let Some( table ) = config.profiles.get( name ) {
+            return Ok( Some( Profile::from_table( name, table )? ) );
+        }
+    }
+    let searched: Vec< String > = files.iter().map( |f| f.to_string_lossy().into_owned() ).collect();
+    anyhow::bail!( "Profile {} not found in {}", name, searched.join( ", " ) )
+}
+
+/// The command line options, with the values of the profile as defaults.
+pub struct Options< 'a > {
+    matches: &'a ArgMatches< 'a >,
+    profile: Option< &'a Profile >,
+    used: RefCell< BTreeSet< String > >,
+}
+
+impl< 'a > Options< 'a > {
+    pub fn new( matches: &'a ArgMatches< 'a >, profile: Option< &'a Profile > ) -> Self {
+        Self {
+            matches,
+            profile,
+            used: RefCell::new( BTreeSet::new() ),
+        }
+    }
+
+    fn profile_values( &self, name: &str ) -> Option< &'a Vec< String > > {
+        self.used.borrow_mut().insert( name.to_string() );
+        if self.matches.values_of( "unset" ).is_some_and( |mut u| u.any( |u| u == name ) ) {
+            return None;
+        }
+        self.profile.and_then( |p| p.values.get( name ) )
+    }
+
+    pub fn value_of( &self, name: &str ) -> Option< &'a str > {
+        let profile = self.profile_values( name ).and_then( |v| v.first() ).map( |v| v.as_str() );
+        self.matches.value_of( name ).or( profile )
+    }
+
+    pub fn values_of( &self, name: &str ) -> Vec< &'a str > {
+        let profile = self.profile_values( name );
+        match self.matches.values_of( name ) {
+            Some( values ) => values.collect(),
+            None => profile.map( |v| v.iter().map( |v| v.as_str() ).collect() ).unwrap_or_default(),
+        }
+    }
+
+    /// Parses the value with `from_name`, profiles are not checked by clap so bad values are caught here.
+    pub fn parse_value_of< T >( &self, name: &str, from_name: fn( &str ) -> Option< T > ) -> anyhow::Result< Option< T > > {
+        match self.value_of( name ) {
+            Some( value ) => match from_name( value ) {
+                Some( v ) => Ok( Some( v ) ),
+                None => anyhow::bail!( "Invalid value {} for {}", value, name ),
+            },
+            None => Ok( None ),
+        }
+    }
+
+    pub fn is_present( &self, name: &str ) -> bool {
+        let profile = self.profile_values( name ).is_some();
+        self.matches.is_present( name ) || profile
+    }
+
+    /// The `requires` of clap, for options that might come from the profile.
+    pub fn check_requires( &self, name: &str, other: &str ) -> anyhow::Result< () > {
+        if self.is_present( name ) && !self.is_present( other ) {
+            anyhow::bail!( "{} can only be used with {}", name, other );
+        }
+        Ok(())
+    }
+
+    /// The `conflicts_with` of clap, for options that might come from the profile.
+    pub fn check_conflict( &self, name: &str, other: &str ) -> anyhow::Result< () > {
+        if self.is_present( name ) && self.is_present( other ) {
+            anyhow::bail!( "{} can not be used with {}", name, other );
+        }
+        Ok(())
+    }
+
+    /// Complains about profile values the command never asked for, most likely typos.
+    pub fn warn_unused( &self ) {
+        if let Some( profile ) = self.profile {
+            let used = self.used.borrow();
+            for key in profile.values.keys().filter( |k| !used.contains( *k ) ) {
+                println!( "WARNING: Profile {} sets {}, which this command does not use", profile.name, key );
+            }
+        }
+    }
+}
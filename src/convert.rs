@@ -0,0 +1,66 @@
+use std::path::Path;
+use crate::archive::load_checksums;
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::manifest_format::{Compression,ManifestFormat};
+
+use async_trait::async_trait;
+
+#[derive(Debug)]
+pub struct Convert {
+    input_file: String,
+    output_file: String,
+    from: Option< ManifestFormat >,
+    to: Option< ManifestFormat >,
+    compression: Option< Compression >,
+    canonical: bool,
+}
+
+impl Convert {
+    pub fn new( input_file: &str, output_file: &str ) -> Self {
+        Self {
+            input_file: input_file.to_string(),
+            output_file: output_file.to_string(),
+            from: None,
+            to: None,
+            compression: None,
+            canonical: false,
+        }
+    }
+
+    pub fn set_from( &mut self, from: ManifestFormat ) {
+        self.from = Some( from );
+    }
+
+    pub fn set_to( &mut self, to: ManifestFormat ) {
+        self.to = Some( to );
+    }
+
+    pub fn set_compression( &mut self, compression: Compression ) {
+        self.compression = Some( compression );
+    }
+
+    pub fn set_canonical( &mut self, canonical: bool ) {
+        self.canonical = canonical;
+    }
+}
+
+#[async_trait]
+impl CommandAsync for Convert {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let mut checksums = match self.from {
+            Some( from ) => Checksums::load_with_format( &self.input_file, from )?,
+            None => load_checksums( &self.input_file )?,
+        };
+        checksums.set_canonical( self.canonical );
+        let to = self.to.unwrap_or_else( || ManifestFormat::from_path( Path::new( &self.output_file ) ) );
+        // checked before the output file is created, we can not calculate other hashes
+        if to == ManifestFormat::Sfv && checksums.algorithm() != "crc32" {
+            anyhow::bail!( "Can not convert {} hashes to SFV, which only holds crc32", checksums.algorithm() );
+        }
+        let compression = self.compression.unwrap_or_else( || Compression::from_path( Path::new( &self.output_file ) ) );
+        checksums.save_with( &self.output_file, to, compression )?;
+        println!( "Converted {} {} entries to {:?}.", checksums.len(), checksums.algorithm(), to );
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::{HashMap,HashSet};
+use std::path::{Path,PathBuf};
+use std::io::{BufReader,Read};
+use serde::Serialize;
+use crate::archive::load_checksums;
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::synchronizer::temp_path;
+
+use async_trait::async_trait;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum LinkMode {
+    Hardlink,
+    Reflink,
+}
+
+impl LinkMode {
+    pub fn from_name( mode: &str ) -> Option< Self > {
+        match mode {
+            "hardlink" => Some( LinkMode::Hardlink ),
+            "reflink" => Some( LinkMode::Reflink ),
+            _ => None,
+        }
+    }
+}
+
+/// Files sharing the same size and hash.
+#[derive(Debug,Serialize)]
+pub struct DuplicateGroup {
+    size: u64,
+    hash: String,
+    wasted: u64,
+    #[serde(with = "crate::portable_path::list")]
+    paths: Vec< PathBuf >,
+}
+
+#[derive(Debug)]
+pub struct Duplicates {
+    checksum_file: Option< String >,
+    base_dir: PathBuf,
+    json_file: Option< String >,
+    link_mode: Option< LinkMode >,
+}
+
+impl Duplicates {
+    pub fn new( base_dir: &Path ) -> Self {
+        Self {
+            checksum_file: None,
+            base_dir: base_dir.to_owned(),
+            json_file: None,
+            link_mode: None,
+        }
+    }
+
+    pub fn set_checksum_file( &mut self, checksum_file: &str ) {
+        self.checksum_file = Some( checksum_file.to_string() )
+    }
+    pub fn set_json_file( &mut self, json_file: &str ) {
+        self.json_file = Some( json_file.to_string() )
+    }
+    pub fn set_link_mode( &mut self, link_mode: LinkMode ) {
+        self.link_mode = Some( link_mode )
+    }
+
+    /// Replaces `duplicate` with a link to `original`, if both are still identical.
+    fn link( &self, original: &Path, duplicate: &Path, link_mode: LinkMode ) -> anyhow::Result< bool > {
+        let original = self.base_dir.join( original );
+        let duplicate = self.base_dir.join( duplicate );
+        if !files_identical( &original, &duplicate )? {
+            return Ok( false );
+        }
+        let temp = temp_path( &duplicate );
+        match link_mode {
+            LinkMode::Hardlink => std::fs::hard_link( &original, &temp )?,
+            LinkMode::Reflink => reflink::reflink( &original, &temp )?,
+        }
+        if let Err( e ) = std::fs::rename( &temp, &duplicate ) {
+            let _ = std::fs::remove_file( &temp );
+            return Err( e.into() );
+        }
+        // renaming onto another link of the same file does nothing, leaving the temp name behind
+        if std::fs::symlink_metadata( &temp ).is_ok() {
+            std::fs::remove_file( &temp )?;
+        }
+        Ok( true )
+    }
+}
+
+/// The device and inode of the file at `path`, to tell hard links apart from copies.
+#[cfg(unix)]
+fn file_id( path: &Path ) -> Option< ( u64, u64 ) > {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata( path ).ok().map( |m| ( m.dev(), m.ino() ) )
+}
+
+#[cfg(not(unix))]
+fn file_id( _path: &Path ) -> Option< ( u64, u64 ) > {
+    None
+}
+
+fn is_same_file( a: &Path, b: &Path ) -> bool {
+    match ( file_id( a ), file_id( b ) ) {
+        ( Some( a ), Some( b ) ) => a == b,
+        _ => false,
+    }
+}
+
+/// Groups the files of `checksums` by content, ignoring groups that are already linked together below `base_dir`.
+pub fn find_duplicates( checksums: &Checksums, base_dir: &Path ) -> Vec< DuplicateGroup > {
+    let mut groups: HashMap< ( u64, &str ), Vec< PathBuf > > = HashMap::new();
+    for e in checksums.entries() {
+        // empty files waste no space, files without a hash could be anything
+        if e.size() == 0 || !e.has_hash() {
+            continue;
+        }
+        groups.entry( ( e.size(), e.hash() ) ).or_default().push( e.path().to_owned() );
+    }
+
+    let mut duplicates: Vec< DuplicateGroup > = groups.into_iter()
+        .filter( |( _, paths )| paths.len() > 1 )
+        .map( |( ( size, hash ), mut paths )| {
+            paths.sort();
+            // hard links of each other waste no space
+            let mut ids = HashSet::new();
+            let copies = paths.iter()
+                .filter( |p| file_id( &base_dir.join( p ) ).is_none_or( |id| ids.insert( id ) ) )
+                .count();
+            DuplicateGroup {
+                size,
+                hash: hash.to_string(),
+                wasted: size * ( copies as u64 - 1 ),
+                paths,
+            }
+        })
+        .filter( |d| d.wasted > 0 )
+        .collect();
+    duplicates.sort_by( |a, b| b.wasted.cmp( &a.wasted ).then_with( || a.paths.cmp( &b.paths ) ) );
+    duplicates
+}
+
+fn files_identical( a: &Path, b: &Path ) -> anyhow::Result< bool > {
+    let mut ra = BufReader::new( std::fs::File::open( a )? );
+    let mut rb = BufReader::new( std::fs::File::open( b )? );
+    let mut buffer_a = vec![0; 64*1024];
+    let mut buffer_b = vec![0; 64*1024];
+    loop {
+        let n = ra.read( &mut buffer_a )?;
+        if n == 0 {
+            return Ok( rb.read( &mut buffer_b[..1] )? == 0 );
+        }
+        if rb.read_exact( &mut buffer_b[..n] ).is_err() || buffer_a[..n] != buffer_b[..n] {
+            return Ok( false );
+        }
+    }
+}
+
+#[async_trait]
+impl CommandAsync for Duplicates {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let checksums = match &self.checksum_file {
+            Some( checksum_file ) => load_checksums( checksum_file )?,
+            None => {
+                let mut checksums = Checksums::new( "sha1" );
+                checksums.add_dir( &self.base_dir )?;
+                checksums.calculate_hashes( &self.base_dir )?;
+                checksums
+            },
+        };
+
+        let duplicates = find_duplicates( &checksums, &self.base_dir );
+        let mut total_wasted = 0;
+        for d in duplicates.iter() {
+            total_wasted += d.wasted;
+            println!( "{} bytes wasted by {} copies of {} bytes ({})", d.wasted, d.paths.len(), d.size, d.hash );
+            for p in d.paths.iter() {
+                println!( "    {}", p.to_string_lossy() );
+            }
+        }
+        println!( "{} groups of duplicates. {} bytes wasted total.", duplicates.len(), total_wasted );
+
+        if let Some( json_file ) = &self.json_file {
+            let json = serde_json::to_string( &duplicates )?;
+            std::fs::write( json_file, json )?;
+        }
+
+        if let Some( link_mode ) = self.link_mode {
+            let mut linked = 0;
+            for d in duplicates.iter() {
+                let ( original, copies ) = d.paths.split_first().expect( "duplicate groups have more than one entry" );
+                for c in copies {
+                    if is_same_file( &self.base_dir.join( original ), &self.base_dir.join( c ) ) {
+                        continue;
+                    }
+                    if self.link( original, c, link_mode )? {
+                        linked += 1;
+                    } else {
+                        println!( "Skipping {}. Content differs from {}.", c.to_string_lossy(), original.to_string_lossy() );
+                    }
+                }
+            }
+            println!( "Replaced {} duplicates with links.", linked );
+        }
+        Ok(())
+    }
+}
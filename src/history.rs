@@ -0,0 +1,264 @@
+use std::collections::{BTreeMap,HashSet};
+use std::path::{Path,PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::compare::{write_path_list,ChangeSet};
+use crate::synchronizer::temp_path;
+
+use async_trait::async_trait;
+
+const HISTORY_INDEX: &str = "index.json";
+
+#[derive(Debug,Clone,Deserialize,Serialize)]
+pub struct SnapshotInfo {
+    id: u64,
+    created: String,
+    source: String,
+    entries: usize,
+    total_size: u64,
+}
+
+/// A snapshot only stores what changed since the previous one.
+#[derive(Debug,Default,Deserialize,Serialize)]
+struct SnapshotDelta {
+    algorithm: String,
+    changed: Vec< ChecksumsEntry >,
+    #[serde(with = "crate::portable_path::list")]
+    removed: Vec< PathBuf >,
+}
+
+#[derive(Debug,Default,Deserialize,Serialize)]
+struct HistoryIndex {
+    snapshots: Vec< SnapshotInfo >,
+}
+
+/// A directory of manifest snapshots.
+#[derive(Debug)]
+pub struct History {
+    dir: PathBuf,
+    index: HistoryIndex,
+}
+
+impl History {
+    pub fn open( dir: &Path ) -> anyhow::Result< Self > {
+        let index_file = dir.join( HISTORY_INDEX );
+        let index = if index_file.exists() {
+            serde_json::from_str( &std::fs::read_to_string( &index_file )? )?
+        } else {
+            HistoryIndex::default()
+        };
+        Ok( Self {
+            dir: dir.to_owned(),
+            index,
+        })
+    }
+
+    pub fn snapshots( &self ) -> &Vec< SnapshotInfo > {
+        &self.index.snapshots
+    }
+
+    fn snapshot_file( &self, id: u64 ) -> PathBuf {
+        self.dir.join( format!( "{:06}.json", id ) )
+    }
+
+    fn load_delta( &self, id: u64 ) -> anyhow::Result< SnapshotDelta > {
+        let json = std::fs::read_to_string( self.snapshot_file( id ) )?;
+        Ok( serde_json::from_str( &json )? )
+    }
+
+    fn replay( &self, id: u64 ) -> anyhow::Result< ( String, BTreeMap< PathBuf, ChecksumsEntry > ) > {
+        let mut algorithm = String::new();
+        let mut entries = BTreeMap::new();
+        for s in self.index.snapshots.iter().take_while( |s| s.id <= id ) {
+            let delta = self.load_delta( s.id )?;
+            algorithm = delta.algorithm;
+            for p in delta.removed {
+                entries.remove( &p );
+            }
+            for e in delta.changed {
+                entries.insert( e.path().to_owned(), e );
+            }
+        }
+        Ok( ( algorithm, entries ) )
+    }
+
+    /// Reconstructs the full manifest of a snapshot.
+    pub fn checkout( &self, id: u64 ) -> anyhow::Result< Checksums > {
+        if !self.index.snapshots.iter().any( |s| s.id == id ) {
+            anyhow::bail!( "No snapshot {} in {:?}", id, self.dir );
+        }
+        let ( algorithm, entries ) = self.replay( id )?;
+        let mut checksums = Checksums::new( &algorithm );
+        for ( _, e ) in entries {
+            checksums.add( e );
+        }
+        Ok( checksums )
+    }
+
+    /// Appends a snapshot, storing only entries that differ from the latest one.
+    pub fn record( &mut self, checksums: &Checksums, source: &str ) -> anyhow::Result< u64 > {
+        std::fs::create_dir_all( &self.dir )?;
+        let ( id, previous ) = match self.index.snapshots.last() {
+            Some( s ) => ( s.id + 1, self.replay( s.id )?.1 ),
+            None => ( 1, BTreeMap::new() ),
+        };
+
+        let mut delta = SnapshotDelta {
+            algorithm: checksums.algorithm().to_string(),
+            ..Default::default()
+        };
+        for e in checksums.entries() {
+            match previous.get( e.path() ) {
+                Some( p ) if p.size() == e.size() && p.hash() == e.hash() && p.mtime() == e.mtime() => {},
+                _ => delta.changed.push( e.clone() ),
+            }
+        }
+        let current: HashSet< &Path > = checksums.entries().iter().map( |e| e.path().as_path() ).collect();
+        for p in previous.keys() {
+            if !current.contains( p.as_path() ) {
+                delta.removed.push( p.to_owned() );
+            }
+        }
+
+        std::fs::write( self.snapshot_file( id ), serde_json::to_string( &delta )? )?;
+        self.index.snapshots.push( SnapshotInfo {
+            id,
+            created: chrono::Local::now().to_rfc3339_opts( chrono::SecondsFormat::Secs, false ),
+            source: source.to_string(),
+            entries: checksums.len(),
+            total_size: checksums.total_size(),
+        });
+        // an interrupted write must not lose the whole history
+        let index_file = self.dir.join( HISTORY_INDEX );
+        let temp = temp_path( &index_file );
+        std::fs::write( &temp, serde_json::to_string( &self.index )? )?;
+        std::fs::rename( &temp, &index_file )?;
+        Ok( id )
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryList {
+    history_dir: PathBuf,
+}
+
+impl HistoryList {
+    pub fn new( history_dir: &Path ) -> Self {
+        Self {
+            history_dir: history_dir.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandAsync for HistoryList {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let history = History::open( &self.history_dir )?;
+        for s in history.snapshots() {
+            println!( "{:6} {} {} files, {} bytes, from {}", s.id, s.created, s.entries, s.total_size, s.source );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryLog {
+    history_dir: PathBuf,
+    path: PathBuf,
+}
+
+impl HistoryLog {
+    pub fn new( history_dir: &Path, path: &Path ) -> Self {
+        Self {
+            history_dir: history_dir.to_owned(),
+            path: path.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandAsync for HistoryLog {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let history = History::open( &self.history_dir )?;
+        let mut last_hash = None;
+        for s in history.snapshots() {
+            let delta = history.load_delta( s.id )?;
+            if let Some( e ) = delta.changed.iter().find( |e| *e.path() == self.path ) {
+                // snapshots also record changes of the mtime only
+                if last_hash.as_deref() != Some( e.hash() ) {
+                    println!( "{:6} {} {} {} bytes", s.id, s.created, e.hash(), e.size() );
+                    last_hash = Some( e.hash().to_string() );
+                }
+            } else if delta.removed.contains( &self.path ) {
+                println!( "{:6} {} removed", s.id, s.created );
+                last_hash = None;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct HistoryDiff {
+    history_dir: PathBuf,
+    snapshot_old: u64,
+    snapshot_new: u64,
+    changed_file: Option< String >,
+    added_file: Option< String >,
+    removed_file: Option< String >,
+}
+
+impl HistoryDiff {
+    pub fn new( history_dir: &Path, snapshot_old: u64, snapshot_new: u64 ) -> Self {
+        Self {
+            history_dir: history_dir.to_owned(),
+            snapshot_old,
+            snapshot_new,
+            changed_file: None,
+            added_file: None,
+            removed_file: None,
+        }
+    }
+
+    pub fn set_changed_file( &mut self, changed_file: &str ) {
+        self.changed_file = Some( changed_file.to_string() )
+    }
+    pub fn set_removed_file( &mut self, removed_file: &str ) {
+        self.removed_file = Some( removed_file.to_string() )
+    }
+    pub fn set_added_file( &mut self, added_file: &str ) {
+        self.added_file = Some( added_file.to_string() )
+    }
+}
+
+#[async_trait]
+impl CommandAsync for HistoryDiff {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let history = History::open( &self.history_dir )?;
+        let old_checksums = history.checkout( self.snapshot_old )?;
+        let new_checksums = history.checkout( self.snapshot_new )?;
+
+        let change_set = ChangeSet::new( &old_checksums, &new_checksums, None );
+        for p in change_set.added.iter() {
+            println!( "added   {}", p.to_string_lossy() );
+        }
+        for p in change_set.changed.iter() {
+            println!( "changed {}", p.to_string_lossy() );
+        }
+        for p in change_set.removed.iter() {
+            println!( "removed {}", p.to_string_lossy() );
+        }
+
+        if let Some( changed_file ) = &self.changed_file {
+            write_path_list( changed_file, &change_set.changed )?;
+        }
+        if let Some( removed_file ) = &self.removed_file {
+            write_path_list( removed_file, &change_set.removed )?;
+        }
+        if let Some( added_file ) = &self.added_file {
+            write_path_list( added_file, &change_set.added )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap,HashMap};
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Path,PathBuf};
+use indicatif::HumanBytes;
+use crate::checksums::ChecksumsEntry;
+use crate::summary::Outcome;
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { font-size: 1.5em; }
+h2 { font-size: 1.2em; margin-top: 2em; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.8em; text-align: left; border-bottom: 1px solid #ddd; }
+table.sortable th { cursor: pointer; user-select: none; }
+table.sortable th[data-order="asc"]::after { content: " \25B2"; }
+table.sortable th[data-order="desc"]::after { content: " \25BC"; }
+td.size { text-align: right; white-space: nowrap; }
+td.hash { font-family: monospace; font-size: 0.85em; word-break: break-all; }
+ul.tree, ul.tree ul { list-style: none; padding-left: 1.2em; }
+ul.tree summary { cursor: pointer; }
+.count { font-size: 0.85em; margin-left: 0.5em; }
+.unchanged { color: #2a7d2a; }
+.changed { color: #b07800; }
+.added { color: #1a6fa8; }
+.removed { color: #c02020; }
+.corrupted, .errored { color: #c02020; font-weight: bold; }
+"#;
+
+const SCRIPT: &str = r#"
+document.querySelectorAll( "table.sortable th" ).forEach( function( th ) {
+    th.addEventListener( "click", function() {
+        var table = th.closest( "table" );
+        var body = table.tBodies[ 0 ];
+        var asc = th.dataset.order !== "asc";
+        table.querySelectorAll( "th" ).forEach( function( h ) { delete h.dataset.order; } );
+        th.dataset.order = asc ? "asc" : "desc";
+        var i = th.cellIndex;
+        var numeric = th.hasAttribute( "data-numeric" );
+        var rows = Array.prototype.slice.call( body.rows );
+        rows.sort( function( a, b ) {
+            var x = a.cells[ i ].dataset.value || a.cells[ i ].textContent;
+            var y = b.cells[ i ].dataset.value || b.cells[ i ].textContent;
+            var c = numeric ? Number( x ) - Number( y ) : x.localeCompare( y );
+            return asc ? c : -c;
+        });
+        rows.forEach( function( r ) { body.appendChild( r ); } );
+    });
+});
+"#;
+
+fn escape( s: &str ) -> String {
+    let mut escaped = String::with_capacity( s.len() );
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str( "&amp;" ),
+            '<' => escaped.push_str( "&lt;" ),
+            '>' => escaped.push_str( "&gt;" ),
+            '"' => escaped.push_str( "&quot;" ),
+            '\'' => escaped.push_str( "&#39;" ),
+            c => escaped.push( c ),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug)]
+struct ReportEntry {
+    outcome: Outcome,
+    path: PathBuf,
+    old: Option< ( u64, String ) >,
+    new: Option< ( u64, String ) >,
+}
+
+#[derive(Debug,Default)]
+struct Dir {
+    dirs: BTreeMap< OsString, Dir >,
+    files: Vec< ( OsString, Outcome ) >,
+    counts: BTreeMap< Outcome, usize >,
+}
+
+impl Dir {
+    fn insert( &mut self, path: &Path, outcome: Outcome ) {
+        *self.counts.entry( outcome ).or_default() += 1;
+        let mut components = path.iter();
+        match ( components.next(), components.as_path() ) {
+            ( Some( name ), rest ) if !rest.as_os_str().is_empty() => {
+                self.dirs.entry( name.to_owned() ).or_default().insert( rest, outcome );
+            },
+            ( Some( name ), _ ) => self.files.push( ( name.to_owned(), outcome ) ),
+            ( None, _ ) => {},
+        }
+    }
+
+    fn has_changes( &self ) -> bool {
+        self.counts.keys().any( |o| *o != Outcome::Unchanged )
+    }
+
+    fn write_counts( &self, html: &mut String ) -> std::fmt::Result {
+        for ( outcome, count ) in self.counts.iter() {
+            write!( html, "<span class=\"count {0}\">{1} {0}</span>", outcome.name(), count )?;
+        }
+        Ok(())
+    }
+
+    fn write( &self, html: &mut String ) -> std::fmt::Result {
+        for ( name, dir ) in self.dirs.iter() {
+            // only directories with changes are worth looking at right away
+            write!( html, "<li><details{}><summary>{}/", if dir.has_changes() { " open" } else { "" }, escape( &name.to_string_lossy() ) )?;
+            dir.write_counts( html )?;
+            html.push_str( "</summary><ul>" );
+            dir.write( html )?;
+            html.push_str( "</ul></details></li>\n" );
+        }
+        let mut files: Vec< &( OsString, Outcome ) > = self.files.iter().collect();
+        files.sort();
+        for ( name, outcome ) in files {
+            writeln!( html, "<li class=\"{0}\">{1} <span class=\"count\">{0}</span></li>", outcome.name(), escape( &name.to_string_lossy() ) )?;
+        }
+        Ok(())
+    }
+}
+
+/// A self-contained HTML page describing the differences, for people who do not read terminals.
+#[derive(Debug)]
+pub struct HtmlReport {
+    title: String,
+    entries: Vec< ReportEntry >,
+}
+
+impl HtmlReport {
+    pub fn new( title: &str ) -> Self {
+        Self {
+            title: title.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn add( &mut self, outcome: Outcome, path: &Path, old: Option< &ChecksumsEntry >, new: Option< &ChecksumsEntry > ) {
+        let details = |e: &ChecksumsEntry| ( e.size(), e.hash().to_string() );
+        self.entries.push( ReportEntry {
+            outcome,
+            path: path.to_owned(),
+            old: old.map( details ),
+            new: new.map( details ),
+        });
+    }
+
+    /// Pairs removed and added files with the same content.
+    fn renames( &self ) -> Vec< ( &ReportEntry, &ReportEntry ) > {
+        let mut added: HashMap< ( u64, &str ), Vec< &ReportEntry > > = HashMap::new();
+        for e in self.entries.iter().filter( |e| e.outcome == Outcome::Added ) {
+            if let Some( ( size, hash ) ) = &e.new {
+                if !hash.is_empty() {
+                    added.entry( ( *size, hash ) ).or_default().push( e );
+                }
+            }
+        }
+        let mut renames = Vec::new();
+        for e in self.entries.iter().filter( |e| e.outcome == Outcome::Removed ) {
+            if let Some( ( size, hash ) ) = &e.old {
+                if let Some( candidates ) = added.get_mut( &( *size, hash.as_str() ) ) {
+                    if !candidates.is_empty() {
+                        renames.push( ( e, candidates.remove( 0 ) ) );
+                    }
+                }
+            }
+        }
+        renames.sort_by( |a, b| a.0.path.cmp( &b.0.path ) );
+        renames
+    }
+
+    fn render( &self ) -> Result< String, std::fmt::Error > {
+        let mut html = String::new();
+        let title = escape( &self.title );
+        writeln!( html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>", title, STYLE )?;
+        writeln!( html, "<h1>{}</h1>\n<p>Generated {}</p>", title, chrono::Local::now().format( "%Y-%m-%d %H:%M:%S" ) )?;
+
+        let mut counts: BTreeMap< Outcome, ( usize, u64 ) > = BTreeMap::new();
+        for e in self.entries.iter() {
+            let c = counts.entry( e.outcome ).or_default();
+            c.0 += 1;
+            c.1 += e.new.as_ref().or( e.old.as_ref() ).map_or( 0, |( size, _ )| *size );
+        }
+        html.push_str( "<h2>Summary</h2>\n<table>\n<tr><th>Status</th><th>Files</th><th>Size</th></tr>\n" );
+        for ( outcome, ( files, size ) ) in counts.iter() {
+            writeln!( html, "<tr class=\"{0}\"><td>{0}</td><td class=\"size\">{1}</td><td class=\"size\">{2}</td></tr>", outcome.name(), files, HumanBytes( *size ) )?;
+        }
+        html.push_str( "</table>\n" );
+
+        html.push_str( "<h2>Changed files</h2>\n<table class=\"sortable\">\n<thead><tr><th>Path</th><th>Status</th><th data-numeric>Old size</th><th data-numeric>New size</th><th>Old hash</th><th>New hash</th></tr></thead>\n<tbody>\n" );
+        let mut changes: Vec< &ReportEntry > = self.entries.iter().filter( |e| e.outcome != Outcome::Unchanged ).collect();
+        changes.sort_by( |a, b| a.path.cmp( &b.path ) );
+        for e in changes {
+            write!( html, "<tr class=\"{}\"><td>{}</td><td>{}</td>", e.outcome.name(), escape( &e.path.to_string_lossy() ), e.outcome.name() )?;
+            for side in [ &e.old, &e.new ].iter() {
+                match side {
+                    Some( ( size, _ ) ) => write!( html, "<td class=\"size\" data-value=\"{}\">{}</td>", size, HumanBytes( *size ) )?,
+                    None => html.push_str( "<td class=\"size\" data-value=\"-1\"></td>" ),
+                }
+            }
+            for side in [ &e.old, &e.new ].iter() {
+                write!( html, "<td class=\"hash\">{}</td>", escape( side.as_ref().map_or( "", |( _, hash )| hash.as_str() ) ) )?;
+            }
+            html.push_str( "</tr>\n" );
+        }
+        html.push_str( "</tbody>\n</table>\n" );
+
+        let renames = self.renames();
+        if !renames.is_empty() {
+            html.push_str( "<h2>Renamed files</h2>\n<table class=\"sortable\">\n<thead><tr><th>From</th><th>To</th><th data-numeric>Size</th><th>Hash</th></tr></thead>\n<tbody>\n" );
+            for ( from, to ) in renames {
+                let ( size, hash ) = from.old.as_ref().map_or( ( 0, "" ), |( s, h )| ( *s, h.as_str() ) );
+                writeln!( html, "<tr><td>{}</td><td>{}</td><td class=\"size\" data-value=\"{}\">{}</td><td class=\"hash\">{}</td></tr>",
+                    escape( &from.path.to_string_lossy() ), escape( &to.path.to_string_lossy() ), size, HumanBytes( size ), escape( hash ) )?;
+            }
+            html.push_str( "</tbody>\n</table>\n" );
+        }
+
+        let mut root = Dir::default();
+        for e in self.entries.iter() {
+            root.insert( &e.path, e.outcome );
+        }
+        html.push_str( "<h2>Files</h2>\n<ul class=\"tree\">\n" );
+        root.write( &mut html )?;
+        html.push_str( "</ul>\n" );
+
+        writeln!( html, "<script>{}</script>\n</body>\n</html>", SCRIPT )?;
+        Ok( html )
+    }
+
+    pub fn save( &self, filename: &str ) -> anyhow::Result< () > {
+        std::fs::write( filename, self.render()? )?;
+        Ok(())
+    }
+}
@@ -1,8 +1,21 @@
 use clap::{Arg,App,SubCommand};
-use std::error::Error;
+use apply::Apply;
+use bundle::Bundle;
 use checksum::Checksum;
 use compare::Compare;
-use verifier::Verifier;
+use compare3::Compare3;
+use compare_many::CompareMany;
+use config::{load_profile,Options};
+use convert::Convert;
+use duplicates::{Duplicates,LinkMode};
+use history::{HistoryDiff,HistoryList,HistoryLog};
+use manifest_format::{Compression,ManifestFormat};
+use manifest::{FilterStatus,ManifestFilter,PathFilter,ManifestMerge,ManifestRebase,ManifestSplit,ManifestStats};
+use path_matching::{PathMatching,UnicodeForm};
+use progress::ProgressMode;
+use query::Query;
+use synchronizer::Synchronizer;
+use verifier::{SuspectedCorruption,Verifier,EXIT_SUSPECTED_CORRUPTION};
 use crate::command_async::CommandAsync;
 
 #[tokio::main]
@@ -23,6 +36,82 @@ pub async fn main() -> anyhow::Result<()> {
                                 .value_name( "base-dir" )
                                 .takes_value( true )
                             )
+                            .arg( Arg::with_name("record")
+                                .long( "record" )
+                                .value_name( "history-dir" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("descend-archives")
+                                .long( "descend-archives" )
+                            )
+                            .arg( Arg::with_name("compress")
+                                .long( "compress" )
+                                .value_name( "compression" )
+                                .takes_value( true )
+                                .possible_values( &[ "none", "gzip", "zstd" ] )
+                            )
+                            .arg( Arg::with_name("canonical")
+                                .long( "canonical" )
+                                .help( "leave out everything that differs between identical trees" )
+                            )
+                            .arg( Arg::with_name("progress")
+                                .long( "progress" )
+                                .value_name( "progress" )
+                                .takes_value( true )
+                                .possible_values( &[ "bars", "json", "none" ] )
+                                .help( "json writes events as lines to stderr, or --progress-fd" )
+                            )
+                            .arg( Arg::with_name("progress-fd")
+                                .long( "progress-fd" )
+                                .value_name( "fd" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("quiet")
+                                .long( "quiet" )
+                                .short( "q" )
+                                .conflicts_with( "progress" )
+                            )
+                            .arg( Arg::with_name("algorithm")
+                                .long( "algorithm" )
+                                .value_name( "algorithm" )
+                                .takes_value( true )
+                                .possible_values( &[ "sha1" ] )
+                            )
+                            .arg( Arg::with_name("threads")
+                                .long( "threads" )
+                                .value_name( "threads" )
+                                .takes_value( true )
+                                .help( "number of files hashed in parallel" )
+                            )
+                            .arg( Arg::with_name("include")
+                                .long( "include" )
+                                .value_name( "glob" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                            )
+                            .arg( Arg::with_name("exclude")
+                                .long( "exclude" )
+                                .value_name( "glob" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                            )
+                            .arg( Arg::with_name("profile")
+                                .long( "profile" )
+                                .value_name( "profile" )
+                                .takes_value( true )
+                                .help( "use the options of this profile from folder-compare.toml as defaults" )
+                            )
+                            .arg( Arg::with_name("unset")
+                                .long( "unset" )
+                                .value_name( "option" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                                .requires( "profile" )
+                                .help( "ignore this option of the profile, e.g. to turn off a flag it sets" )
+                            )
                         )
                         .subcommand( SubCommand::with_name("verify")
                             .arg( Arg::with_name("checksum-file")
@@ -35,6 +124,34 @@ pub async fn main() -> anyhow::Result<()> {
                                 .value_name( "base-dir" )
                                 .takes_value( true )
                             )
+                            .arg( Arg::with_name("descend-archives")
+                                .long( "descend-archives" )
+                            )
+                            .arg( Arg::with_name("normalize-unicode")
+                                .long( "normalize-unicode" )
+                                .value_name( "normalize-unicode" )
+                                .takes_value( true )
+                                .possible_values( &[ "nfc", "nfd" ] )
+                            )
+                            .arg( Arg::with_name("case-insensitive")
+                                .long( "case-insensitive" )
+                            )
+                            .arg( Arg::with_name("scrub")
+                                .long( "scrub" )
+                            )
+                            .arg( Arg::with_name("corrupted-file")
+                                .long( "corrupted-file" )
+                                .value_name( "corrupted-file" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("watch")
+                                .long( "watch" )
+                            )
+                            .arg( Arg::with_name("hook")
+                                .long( "hook" )
+                                .value_name( "hook" )
+                                .takes_value( true )
+                            )
                             .arg( Arg::with_name("changed-file")
                                 .long( "changed-file" )
                                 .value_name( "changed-file" )
@@ -50,6 +167,79 @@ pub async fn main() -> anyhow::Result<()> {
                                 .value_name( "removed-file" )
                                 .takes_value( true )
                             )
+                            .arg( Arg::with_name("progress")
+                                .long( "progress" )
+                                .value_name( "progress" )
+                                .takes_value( true )
+                                .possible_values( &[ "bars", "json", "none" ] )
+                                .help( "json writes events as lines to stderr, or --progress-fd" )
+                            )
+                            .arg( Arg::with_name("progress-fd")
+                                .long( "progress-fd" )
+                                .value_name( "fd" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("quiet")
+                                .long( "quiet" )
+                                .short( "q" )
+                                .conflicts_with( "progress" )
+                            )
+                            .arg( Arg::with_name("verbose")
+                                .long( "verbose" )
+                                .short( "v" )
+                                .help( "list every file by status" )
+                            )
+                            .arg( Arg::with_name("html-report")
+                                .long( "html-report" )
+                                .value_name( "html-report" )
+                                .takes_value( true )
+                                .help( "write a self-contained HTML page describing the differences" )
+                            )
+                            .arg( Arg::with_name("junit")
+                                .long( "junit" )
+                                .value_name( "junit-file" )
+                                .takes_value( true )
+                                .help( "write JUnit XML with one test case per file in the manifest" )
+                            )
+                            .arg( Arg::with_name("sarif")
+                                .long( "sarif" )
+                                .value_name( "sarif-file" )
+                                .takes_value( true )
+                                .help( "write a SARIF log with one result per mismatch" )
+                            )
+                            .arg( Arg::with_name("fail-on-added")
+                                .long( "fail-on-added" )
+                                .help( "report added files as failures in --junit and --sarif" )
+                            )
+                            .arg( Arg::with_name("include")
+                                .long( "include" )
+                                .value_name( "glob" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                            )
+                            .arg( Arg::with_name("exclude")
+                                .long( "exclude" )
+                                .value_name( "glob" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                            )
+                            .arg( Arg::with_name("profile")
+                                .long( "profile" )
+                                .value_name( "profile" )
+                                .takes_value( true )
+                                .help( "use the options of this profile from folder-compare.toml as defaults" )
+                            )
+                            .arg( Arg::with_name("unset")
+                                .long( "unset" )
+                                .value_name( "option" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                                .requires( "profile" )
+                                .help( "ignore this option of the profile, e.g. to turn off a flag it sets" )
+                            )
                         )
                         .subcommand( SubCommand::with_name("compare")
                             .arg( Arg::with_name("checksum-file-old")
@@ -62,6 +252,15 @@ pub async fn main() -> anyhow::Result<()> {
                                 .value_name( "checksum-file-new" )
                                 .takes_value( true )
                             )
+                            .arg( Arg::with_name("normalize-unicode")
+                                .long( "normalize-unicode" )
+                                .value_name( "normalize-unicode" )
+                                .takes_value( true )
+                                .possible_values( &[ "nfc", "nfd" ] )
+                            )
+                            .arg( Arg::with_name("case-insensitive")
+                                .long( "case-insensitive" )
+                            )
                             .arg( Arg::with_name("changed-file")
                                 .long( "changed-file" )
                                 .value_name( "changed-file" )
@@ -77,68 +276,731 @@ pub async fn main() -> anyhow::Result<()> {
                                 .value_name( "removed-file" )
                                 .takes_value( true )
                             )
+                            .arg( Arg::with_name("progress")
+                                .long( "progress" )
+                                .value_name( "progress" )
+                                .takes_value( true )
+                                .possible_values( &[ "bars", "json", "none" ] )
+                                .help( "json writes events as lines to stderr, or --progress-fd" )
+                            )
+                            .arg( Arg::with_name("progress-fd")
+                                .long( "progress-fd" )
+                                .value_name( "fd" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("quiet")
+                                .long( "quiet" )
+                                .short( "q" )
+                                .conflicts_with( "progress" )
+                            )
+                            .arg( Arg::with_name("verbose")
+                                .long( "verbose" )
+                                .short( "v" )
+                                .help( "list every file by status" )
+                            )
+                            .arg( Arg::with_name("html-report")
+                                .long( "html-report" )
+                                .value_name( "html-report" )
+                                .takes_value( true )
+                                .help( "write a self-contained HTML page describing the differences" )
+                            )
+                            .arg( Arg::with_name("profile")
+                                .long( "profile" )
+                                .value_name( "profile" )
+                                .takes_value( true )
+                                .help( "use the options of this profile from folder-compare.toml as defaults" )
+                            )
+                            .arg( Arg::with_name("unset")
+                                .long( "unset" )
+                                .value_name( "option" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                                .requires( "profile" )
+                                .help( "ignore this option of the profile, e.g. to turn off a flag it sets" )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("compare3")
+                            .arg( Arg::with_name("base")
+                                .long( "base" )
+                                .value_name( "base" )
+                                .takes_value( true )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("ours")
+                                .long( "ours" )
+                                .value_name( "ours" )
+                                .takes_value( true )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("theirs")
+                                .long( "theirs" )
+                                .value_name( "theirs" )
+                                .takes_value( true )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("report-file")
+                                .long( "report-file" )
+                                .value_name( "report-file" )
+                                .takes_value( true )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("compare-many")
+                            .arg( Arg::with_name("checksum-files")
+                                .index( 1 )
+                                .multiple( true )
+                                .min_values( 2 )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("report-file")
+                                .long( "report-file" )
+                                .value_name( "report-file" )
+                                .takes_value( true )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("bundle")
+                            .arg( Arg::with_name("checksum-file-old")
+                                .long( "checksum-file-old" )
+                                .value_name( "checksum-file-old" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("base-dir")
+                                .long( "base-dir" )
+                                .value_name( "base-dir" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("bundle-file")
+                                .long( "bundle-file" )
+                                .value_name( "bundle-file" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("zstd")
+                                .long( "zstd" )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("apply")
+                            .arg( Arg::with_name("bundle-file")
+                                .long( "bundle-file" )
+                                .value_name( "bundle-file" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("base-dir")
+                                .long( "base-dir" )
+                                .value_name( "base-dir" )
+                                .takes_value( true )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("sync")
+                            .arg( Arg::with_name("source")
+                                .long( "source" )
+                                .value_name( "source" )
+                                .takes_value( true )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("target")
+                                .long( "target" )
+                                .value_name( "target" )
+                                .takes_value( true )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("delete")
+                                .long( "delete" )
+                            )
+                            .arg( Arg::with_name("dry-run")
+                                .long( "dry-run" )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("duplicates")
+                            .arg( Arg::with_name("checksum-file")
+                                .long( "checksum-file" )
+                                .value_name( "checksum-file" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("base-dir")
+                                .long( "base-dir" )
+                                .value_name( "base-dir" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("json-file")
+                                .long( "json-file" )
+                                .value_name( "json-file" )
+                                .takes_value( true )
+                            )
+                            .arg( Arg::with_name("link")
+                                .long( "link" )
+                                .value_name( "link" )
+                                .takes_value( true )
+                                .possible_values( &[ "hardlink", "reflink" ] )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("history")
+                            .subcommand( SubCommand::with_name("list")
+                                .arg( Arg::with_name("history-dir")
+                                    .long( "history-dir" )
+                                    .value_name( "history-dir" )
+                                    .takes_value( true )
+                                )
+                            )
+                            .subcommand( SubCommand::with_name("log")
+                                .arg( Arg::with_name("history-dir")
+                                    .long( "history-dir" )
+                                    .value_name( "history-dir" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("path")
+                                    .index( 1 )
+                                    .required( true )
+                                )
+                            )
+                            .subcommand( SubCommand::with_name("diff")
+                                .arg( Arg::with_name("history-dir")
+                                    .long( "history-dir" )
+                                    .value_name( "history-dir" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("snapshot-old")
+                                    .index( 1 )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("snapshot-new")
+                                    .index( 2 )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("changed-file")
+                                    .long( "changed-file" )
+                                    .value_name( "changed-file" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("added-file")
+                                    .long( "added-file" )
+                                    .value_name( "added-file" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("removed-file")
+                                    .long( "removed-file" )
+                                    .value_name( "removed-file" )
+                                    .takes_value( true )
+                                )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("convert")
+                            .arg( Arg::with_name("from")
+                                .long( "from" )
+                                .value_name( "format" )
+                                .takes_value( true )
+                                .possible_values( &[ "json", "ndjson", "csv", "sum", "hashdeep", "sfv", "binary", "sqlite" ] )
+                            )
+                            .arg( Arg::with_name("to")
+                                .long( "to" )
+                                .value_name( "format" )
+                                .takes_value( true )
+                                .possible_values( &[ "json", "ndjson", "csv", "sum", "hashdeep", "sfv", "binary", "sqlite" ] )
+                            )
+                            .arg( Arg::with_name("compress")
+                                .long( "compress" )
+                                .value_name( "compression" )
+                                .takes_value( true )
+                                .possible_values( &[ "none", "gzip", "zstd" ] )
+                            )
+                            .arg( Arg::with_name("canonical")
+                                .long( "canonical" )
+                                .help( "leave out everything that differs between identical trees" )
+                            )
+                            .arg( Arg::with_name("input-file")
+                                .index( 1 )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("output-file")
+                                .index( 2 )
+                                .required( true )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("query")
+                            .arg( Arg::with_name("checksum-file")
+                                .long( "checksum-file" )
+                                .value_name( "[name=]checksum-file" )
+                                .help( "manifest to query as database name, default manifest" )
+                                .takes_value( true )
+                                .multiple( true )
+                                .number_of_values( 1 )
+                                .required( true )
+                            )
+                            .arg( Arg::with_name("sql")
+                                .index( 1 )
+                                .required( true )
+                            )
+                        )
+                        .subcommand( SubCommand::with_name("manifest")
+                            .subcommand( SubCommand::with_name("merge")
+                                .arg( Arg::with_name("output-file")
+                                    .long( "output-file" )
+                                    .value_name( "output-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("inputs")
+                                    .help( "checksum files to merge, as [prefix=]checksum-file" )
+                                    .index( 1 )
+                                    .multiple( true )
+                                    .required( true )
+                                )
+                            )
+                            .subcommand( SubCommand::with_name("filter")
+                                .arg( Arg::with_name("checksum-file")
+                                    .long( "checksum-file" )
+                                    .value_name( "checksum-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("output-file")
+                                    .long( "output-file" )
+                                    .value_name( "output-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("include")
+                                    .long( "include" )
+                                    .value_name( "glob" )
+                                    .takes_value( true )
+                                    .multiple( true )
+                                    .number_of_values( 1 )
+                                )
+                                .arg( Arg::with_name("exclude")
+                                    .long( "exclude" )
+                                    .value_name( "glob" )
+                                    .takes_value( true )
+                                    .multiple( true )
+                                    .number_of_values( 1 )
+                                )
+                                .arg( Arg::with_name("min-size")
+                                    .long( "min-size" )
+                                    .value_name( "bytes" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("max-size")
+                                    .long( "max-size" )
+                                    .value_name( "bytes" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("status")
+                                    .long( "status" )
+                                    .value_name( "status" )
+                                    .takes_value( true )
+                                    .possible_values( &[ "added", "changed", "unchanged" ] )
+                                    .requires( "checksum-file-old" )
+                                )
+                                .arg( Arg::with_name("checksum-file-old")
+                                    .long( "checksum-file-old" )
+                                    .value_name( "checksum-file-old" )
+                                    .takes_value( true )
+                                    .requires( "status" )
+                                )
+                            )
+                            .subcommand( SubCommand::with_name("rebase")
+                                .arg( Arg::with_name("checksum-file")
+                                    .long( "checksum-file" )
+                                    .value_name( "checksum-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("output-file")
+                                    .long( "output-file" )
+                                    .value_name( "output-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("strip-prefix")
+                                    .long( "strip-prefix" )
+                                    .value_name( "prefix" )
+                                    .takes_value( true )
+                                )
+                                .arg( Arg::with_name("add-prefix")
+                                    .long( "add-prefix" )
+                                    .value_name( "prefix" )
+                                    .takes_value( true )
+                                )
+                            )
+                            .subcommand( SubCommand::with_name("split")
+                                .arg( Arg::with_name("checksum-file")
+                                    .long( "checksum-file" )
+                                    .value_name( "checksum-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                                .arg( Arg::with_name("output-dir")
+                                    .long( "output-dir" )
+                                    .value_name( "output-dir" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                            )
+                            .subcommand( SubCommand::with_name("stats")
+                                .arg( Arg::with_name("checksum-file")
+                                    .long( "checksum-file" )
+                                    .value_name( "checksum-file" )
+                                    .takes_value( true )
+                                    .required( true )
+                                )
+                            )
                         )
                         .get_matches();
 
         let mut command: Box< dyn CommandAsync > = if let ( "checksum", Some( sub_matches ) ) = matches.subcommand() {
-            let checksum_file = sub_matches.value_of( "checksum-file" ).unwrap_or("checksum.json").to_string();
-            let base_dir = std::fs::canonicalize(sub_matches.value_of( "base-dir" ).unwrap_or(".").to_string()).expect( "base-dir is invalid");
+            let profile = load_profile( sub_matches )?;
+            let options = Options::new( sub_matches, profile.as_ref() );
+            let checksum_file = options.value_of( "checksum-file" ).unwrap_or("checksum.json").to_string();
+            let base_dir = std::fs::canonicalize(options.value_of( "base-dir" ).unwrap_or(".")).expect( "base-dir is invalid");
             let mut checksum = Checksum::new( &checksum_file, &base_dir );
+            checksum.set_descend_archives( options.is_present( "descend-archives" ) );
+            if let Some( history_dir ) = options.value_of( "record" ) {
+                checksum.set_history_dir( std::path::Path::new( history_dir ) );
+            }
+            if let Some( compression ) = options.parse_value_of( "compress", Compression::from_name )? {
+                checksum.set_compression( compression );
+            }
+            checksum.set_canonical( options.is_present( "canonical" ) );
+            if let Some( algorithm ) = options.value_of( "algorithm" ) {
+                checksum.set_algorithm( algorithm );
+            }
+            if let Some( threads ) = options.value_of( "threads" ) {
+                checksum.set_threads( threads.parse()? );
+            }
+            checksum.set_filter( path_filter( &options )? );
+            if let Some( progress ) = options.parse_value_of( "progress", ProgressMode::from_name )? {
+                checksum.set_progress( progress );
+            }
+            if let Some( progress_fd ) = options.value_of( "progress-fd" ) {
+                checksum.set_progress_fd( progress_fd.parse()? );
+            }
+            options.check_requires( "progress-fd", "progress" )?;
+            options.check_conflict( "quiet", "progress" )?;
+            if options.is_present( "quiet" ) {
+                checksum.set_progress( ProgressMode::Quiet );
+            }
+            options.warn_unused();
             //checksum.run().await;
             Box::new( checksum )
         } else if let ( "verify", Some( sub_matches ) ) = matches.subcommand() {
-            let checksum_file = sub_matches.value_of( "checksum-file" ).unwrap_or("checksum.json").to_string();
-            let base_dir = std::fs::canonicalize(sub_matches.value_of( "base-dir" ).unwrap_or(".").to_string()).expect( "base-dir is invalid");
-            let changed_file = sub_matches.value_of( "changed-file" ).unwrap_or("").to_string();
-            let added_file = sub_matches.value_of( "added-file" ).unwrap_or("").to_string();
-            let removed_file = sub_matches.value_of( "removed-file" ).unwrap_or("").to_string();
+            let profile = load_profile( sub_matches )?;
+            let options = Options::new( sub_matches, profile.as_ref() );
+            let checksum_file = options.value_of( "checksum-file" ).unwrap_or("checksum.json").to_string();
+            let base_dir = std::fs::canonicalize(options.value_of( "base-dir" ).unwrap_or(".")).expect( "base-dir is invalid");
+            let changed_file = options.value_of( "changed-file" ).unwrap_or("").to_string();
+            let added_file = options.value_of( "added-file" ).unwrap_or("").to_string();
+            let removed_file = options.value_of( "removed-file" ).unwrap_or("").to_string();
             let mut checksum = Verifier::new( &checksum_file, &base_dir );
-            if changed_file != "" {
+            checksum.set_descend_archives( options.is_present( "descend-archives" ) );
+            checksum.set_path_matching( path_matching( &options )? );
+            checksum.set_scrub( options.is_present( "scrub" ) );
+            checksum.set_filter( path_filter( &options )? );
+            if let Some( corrupted_file ) = options.value_of( "corrupted-file" ) {
+                checksum.set_corrupted_file( corrupted_file );
+            }
+            checksum.set_watch( options.is_present( "watch" ) );
+            if let Some( hook ) = options.value_of( "hook" ) {
+                checksum.set_hook( hook );
+            }
+            if !changed_file.is_empty() {
                 checksum.set_changed_file( &changed_file );
             }
-            if added_file != "" {
+            if !added_file.is_empty() {
                 checksum.set_added_file( &added_file );
             }
-            if removed_file != "" {
+            if !removed_file.is_empty() {
                 checksum.set_removed_file( &removed_file );
             }
+            if let Some( progress ) = options.parse_value_of( "progress", ProgressMode::from_name )? {
+                checksum.set_progress( progress );
+            }
+            if let Some( progress_fd ) = options.value_of( "progress-fd" ) {
+                checksum.set_progress_fd( progress_fd.parse()? );
+            }
+            options.check_requires( "progress-fd", "progress" )?;
+            options.check_requires( "corrupted-file", "scrub" )?;
+            options.check_requires( "hook", "watch" )?;
+            options.check_conflict( "quiet", "progress" )?;
+            if options.is_present( "quiet" ) {
+                checksum.set_progress( ProgressMode::Quiet );
+            }
+            checksum.set_verbose( options.is_present( "verbose" ) );
+            if let Some( html_report ) = options.value_of( "html-report" ) {
+                checksum.set_html_report( html_report );
+            }
+            if let Some( junit_file ) = options.value_of( "junit" ) {
+                checksum.set_junit_file( junit_file );
+            }
+            if let Some( sarif_file ) = options.value_of( "sarif" ) {
+                checksum.set_sarif_file( sarif_file );
+            }
+            checksum.set_fail_on_added( options.is_present( "fail-on-added" ) );
 
+            options.warn_unused();
             //checksum.run().await;
             Box::new( checksum )
         } else if let ( "compare", Some( sub_matches ) ) = matches.subcommand() {
-            let checksum_file_old = sub_matches.value_of( "checksum-file-old" ).unwrap_or("old-checksum.json").to_string();
-            let checksum_file_new = sub_matches.value_of( "checksum-file-new" ).unwrap_or("new-checksum.json").to_string();
-            let changed_file = sub_matches.value_of( "changed-file" ).unwrap_or("").to_string();
-            let added_file = sub_matches.value_of( "added-file" ).unwrap_or("").to_string();
-            let removed_file = sub_matches.value_of( "removed-file" ).unwrap_or("").to_string();
+            let profile = load_profile( sub_matches )?;
+            let options = Options::new( sub_matches, profile.as_ref() );
+            let checksum_file_old = options.value_of( "checksum-file-old" ).unwrap_or("old-checksum.json").to_string();
+            let checksum_file_new = options.value_of( "checksum-file-new" ).unwrap_or("new-checksum.json").to_string();
+            let changed_file = options.value_of( "changed-file" ).unwrap_or("").to_string();
+            let added_file = options.value_of( "added-file" ).unwrap_or("").to_string();
+            let removed_file = options.value_of( "removed-file" ).unwrap_or("").to_string();
             let mut checksum = Compare::new( &checksum_file_old, &checksum_file_new );
-            if changed_file != "" {
+            checksum.set_path_matching( path_matching( &options )? );
+            if !changed_file.is_empty() {
                 checksum.set_changed_file( &changed_file );
             }
-            if added_file != "" {
+            if !added_file.is_empty() {
                 checksum.set_added_file( &added_file );
             }
-            if removed_file != "" {
+            if !removed_file.is_empty() {
                 checksum.set_removed_file( &removed_file );
             }
+            if let Some( progress ) = options.parse_value_of( "progress", ProgressMode::from_name )? {
+                checksum.set_progress( progress );
+            }
+            if let Some( progress_fd ) = options.value_of( "progress-fd" ) {
+                checksum.set_progress_fd( progress_fd.parse()? );
+            }
+            options.check_requires( "progress-fd", "progress" )?;
+            options.check_conflict( "quiet", "progress" )?;
+            if options.is_present( "quiet" ) {
+                checksum.set_progress( ProgressMode::Quiet );
+            }
+            checksum.set_verbose( options.is_present( "verbose" ) );
+            if let Some( html_report ) = options.value_of( "html-report" ) {
+                checksum.set_html_report( html_report );
+            }
 
+            options.warn_unused();
             //checksum.run().await;
             Box::new( checksum )
+        } else if let ( "compare3", Some( sub_matches ) ) = matches.subcommand() {
+            let base = sub_matches.value_of( "base" ).unwrap_or("").to_string();
+            let ours = sub_matches.value_of( "ours" ).unwrap_or("").to_string();
+            let theirs = sub_matches.value_of( "theirs" ).unwrap_or("").to_string();
+            let mut compare3 = Compare3::new( &base, &ours, &theirs );
+            if let Some( report_file ) = sub_matches.value_of( "report-file" ) {
+                compare3.set_report_file( report_file );
+            }
+            Box::new( compare3 )
+        } else if let ( "compare-many", Some( sub_matches ) ) = matches.subcommand() {
+            let checksum_files: Vec< String > = sub_matches.values_of( "checksum-files" ).unwrap_or_default().map( |f| f.to_string() ).collect();
+            let mut compare_many = CompareMany::new( &checksum_files );
+            if let Some( report_file ) = sub_matches.value_of( "report-file" ) {
+                compare_many.set_report_file( report_file );
+            }
+            Box::new( compare_many )
+        } else if let ( "bundle", Some( sub_matches ) ) = matches.subcommand() {
+            let checksum_file_old = sub_matches.value_of( "checksum-file-old" ).unwrap_or("old-checksum.json").to_string();
+            let base_dir = std::fs::canonicalize(sub_matches.value_of( "base-dir" ).unwrap_or(".")).expect( "base-dir is invalid");
+            let zstd = sub_matches.is_present( "zstd" );
+            let default_bundle_file = if zstd { "bundle.tar.zst" } else { "bundle.tar" };
+            let bundle_file = sub_matches.value_of( "bundle-file" ).unwrap_or(default_bundle_file).to_string();
+            let mut bundle = Bundle::new( &checksum_file_old, &base_dir, &bundle_file );
+            bundle.set_zstd( zstd );
+            Box::new( bundle )
+        } else if let ( "apply", Some( sub_matches ) ) = matches.subcommand() {
+            let bundle_file = sub_matches.value_of( "bundle-file" ).unwrap_or("bundle.tar").to_string();
+            let base_dir = std::fs::canonicalize(sub_matches.value_of( "base-dir" ).unwrap_or(".")).expect( "base-dir is invalid");
+            let apply = Apply::new( &bundle_file, &base_dir );
+            Box::new( apply )
+        } else if let ( "sync", Some( sub_matches ) ) = matches.subcommand() {
+            let source_dir = std::fs::canonicalize(sub_matches.value_of( "source" ).unwrap_or(".")).expect( "source is invalid");
+            let target_dir = std::path::PathBuf::from( sub_matches.value_of( "target" ).unwrap_or(".") );
+            let mut synchronizer = Synchronizer::new( &source_dir, &target_dir );
+            synchronizer.set_delete( sub_matches.is_present( "delete" ) );
+            synchronizer.set_dry_run( sub_matches.is_present( "dry-run" ) );
+            Box::new( synchronizer )
+        } else if let ( "duplicates", Some( sub_matches ) ) = matches.subcommand() {
+            let base_dir = std::fs::canonicalize(sub_matches.value_of( "base-dir" ).unwrap_or(".")).expect( "base-dir is invalid");
+            let mut duplicates = Duplicates::new( &base_dir );
+            if let Some( checksum_file ) = sub_matches.value_of( "checksum-file" ) {
+                duplicates.set_checksum_file( checksum_file );
+            }
+            if let Some( json_file ) = sub_matches.value_of( "json-file" ) {
+                duplicates.set_json_file( json_file );
+            }
+            if let Some( link_mode ) = sub_matches.value_of( "link" ).and_then( LinkMode::from_name ) {
+                duplicates.set_link_mode( link_mode );
+            }
+            Box::new( duplicates )
+        } else if let ( "history", Some( sub_matches ) ) = matches.subcommand() {
+            match sub_matches.subcommand() {
+                ( "list", Some( sub_matches ) ) => {
+                    let history_dir = std::path::PathBuf::from( sub_matches.value_of( "history-dir" ).unwrap_or("history") );
+                    Box::new( HistoryList::new( &history_dir ) )
+                },
+                ( "log", Some( sub_matches ) ) => {
+                    let history_dir = std::path::PathBuf::from( sub_matches.value_of( "history-dir" ).unwrap_or("history") );
+                    let path = std::path::PathBuf::from( sub_matches.value_of( "path" ).unwrap_or("") );
+                    Box::new( HistoryLog::new( &history_dir, &path ) )
+                },
+                ( "diff", Some( sub_matches ) ) => {
+                    let history_dir = std::path::PathBuf::from( sub_matches.value_of( "history-dir" ).unwrap_or("history") );
+                    let snapshot_old = sub_matches.value_of( "snapshot-old" ).unwrap_or("").parse::< u64 >()?;
+                    let snapshot_new = sub_matches.value_of( "snapshot-new" ).unwrap_or("").parse::< u64 >()?;
+                    let mut diff = HistoryDiff::new( &history_dir, snapshot_old, snapshot_new );
+                    if let Some( changed_file ) = sub_matches.value_of( "changed-file" ) {
+                        diff.set_changed_file( changed_file );
+                    }
+                    if let Some( added_file ) = sub_matches.value_of( "added-file" ) {
+                        diff.set_added_file( added_file );
+                    }
+                    if let Some( removed_file ) = sub_matches.value_of( "removed-file" ) {
+                        diff.set_removed_file( removed_file );
+                    }
+                    Box::new( diff )
+                },
+                _ => {
+                    println!("No history comand given. Try help!");
+                    std::process::exit( -1 );
+                },
+            }
+        } else if let ( "convert", Some( sub_matches ) ) = matches.subcommand() {
+            let input_file = sub_matches.value_of( "input-file" ).unwrap_or("");
+            let output_file = sub_matches.value_of( "output-file" ).unwrap_or("");
+            let mut convert = Convert::new( input_file, output_file );
+            if let Some( from ) = sub_matches.value_of( "from" ).and_then( ManifestFormat::from_name ) {
+                convert.set_from( from );
+            }
+            if let Some( to ) = sub_matches.value_of( "to" ).and_then( ManifestFormat::from_name ) {
+                convert.set_to( to );
+            }
+            if let Some( compression ) = sub_matches.value_of( "compress" ).and_then( Compression::from_name ) {
+                convert.set_compression( compression );
+            }
+            convert.set_canonical( sub_matches.is_present( "canonical" ) );
+            Box::new( convert )
+        } else if let ( "query", Some( sub_matches ) ) = matches.subcommand() {
+            let mut query = Query::new( sub_matches.value_of( "sql" ).unwrap_or("") );
+            for manifest in sub_matches.values_of( "checksum-file" ).into_iter().flatten() {
+                match manifest.find( '=' ) {
+                    Some( i ) => query.add_manifest( &manifest[ ..i ], &manifest[ i+1.. ] )?,
+                    None => query.add_manifest( "manifest", manifest )?,
+                }
+            }
+            Box::new( query )
+        } else if let ( "manifest", Some( sub_matches ) ) = matches.subcommand() {
+            match sub_matches.subcommand() {
+                ( "merge", Some( sub_matches ) ) => {
+                    let mut merge = ManifestMerge::new( sub_matches.value_of( "output-file" ).unwrap_or("") );
+                    for input in sub_matches.values_of( "inputs" ).into_iter().flatten() {
+                        match input.find( '=' ) {
+                            Some( i ) => merge.add_input( &input[ i+1.. ], std::path::Path::new( &input[ ..i ] ) ),
+                            None => merge.add_input( input, std::path::Path::new( "" ) ),
+                        }
+                    }
+                    Box::new( merge )
+                },
+                ( "filter", Some( sub_matches ) ) => {
+                    let checksum_file = sub_matches.value_of( "checksum-file" ).unwrap_or("");
+                    let output_file = sub_matches.value_of( "output-file" ).unwrap_or("");
+                    let mut filter = ManifestFilter::new( checksum_file, output_file );
+                    for pattern in sub_matches.values_of( "include" ).into_iter().flatten() {
+                        filter.add_include( pattern )?;
+                    }
+                    for pattern in sub_matches.values_of( "exclude" ).into_iter().flatten() {
+                        filter.add_exclude( pattern )?;
+                    }
+                    if let Some( min_size ) = sub_matches.value_of( "min-size" ) {
+                        filter.set_min_size( min_size.parse()? );
+                    }
+                    if let Some( max_size ) = sub_matches.value_of( "max-size" ) {
+                        filter.set_max_size( max_size.parse()? );
+                    }
+                    if let Some( status ) = sub_matches.value_of( "status" ).and_then( FilterStatus::from_name ) {
+                        filter.set_status( status, sub_matches.value_of( "checksum-file-old" ).unwrap_or("") );
+                    }
+                    Box::new( filter )
+                },
+                ( "rebase", Some( sub_matches ) ) => {
+                    let checksum_file = sub_matches.value_of( "checksum-file" ).unwrap_or("");
+                    let output_file = sub_matches.value_of( "output-file" ).unwrap_or("");
+                    let mut rebase = ManifestRebase::new( checksum_file, output_file );
+                    if let Some( strip_prefix ) = sub_matches.value_of( "strip-prefix" ) {
+                        rebase.set_strip_prefix( std::path::Path::new( strip_prefix ) );
+                    }
+                    if let Some( add_prefix ) = sub_matches.value_of( "add-prefix" ) {
+                        rebase.set_add_prefix( std::path::Path::new( add_prefix ) );
+                    }
+                    Box::new( rebase )
+                },
+                ( "split", Some( sub_matches ) ) => {
+                    let checksum_file = sub_matches.value_of( "checksum-file" ).unwrap_or("");
+                    let output_dir = std::path::PathBuf::from( sub_matches.value_of( "output-dir" ).unwrap_or("") );
+                    Box::new( ManifestSplit::new( checksum_file, &output_dir ) )
+                },
+                ( "stats", Some( sub_matches ) ) => {
+                    Box::new( ManifestStats::new( sub_matches.value_of( "checksum-file" ).unwrap_or("") ) )
+                },
+                _ => {
+                    println!("No manifest comand given. Try help!");
+                    std::process::exit( -1 );
+                },
+            }
         } else {
             println!("No comand given. Try help!");
             std::process::exit( -1 );
         };
 
 //        dbg!(&command);
-        command.run().await?;
+        if let Err( e ) = command.run().await {
+            if let Some( c ) = e.downcast_ref::< SuspectedCorruption >() {
+                println!( "ERROR: {}", c );
+                std::process::exit( EXIT_SUSPECTED_CORRUPTION );
+            }
+            return Err( e );
+        }
 
         Ok(())
 }
 
+fn path_filter( options: &Options ) -> anyhow::Result< PathFilter > {
+    let mut filter = PathFilter::default();
+    for pattern in options.values_of( "include" ) {
+        filter.add_include( pattern )?;
+    }
+    for pattern in options.values_of( "exclude" ) {
+        filter.add_exclude( pattern )?;
+    }
+    Ok( filter )
+}
+
+fn path_matching( options: &Options ) -> anyhow::Result< PathMatching > {
+    let mut path_matching = PathMatching::default();
+    if let Some( form ) = options.parse_value_of( "normalize-unicode", UnicodeForm::from_name )? {
+        path_matching.set_normalize_unicode( form );
+    }
+    path_matching.set_case_insensitive( options.is_present( "case-insensitive" ) );
+    Ok( path_matching )
+}
 
+mod apply;
+mod archive;
+mod binary_manifest;
+mod bundle;
 mod checksum;
 mod checksums;
+mod ci_report;
 mod compare;
+mod config;
+mod compare3;
+mod compare_many;
+mod convert;
+mod duplicates;
+mod history;
+mod html_report;
+mod manifest;
+mod manifest_format;
+mod path_matching;
+mod portable_path;
+mod progress;
+mod query;
+mod sqlite_manifest;
+mod summary;
+mod synchronizer;
 mod verifier;
+mod watch;
 mod command_async;
 mod message;
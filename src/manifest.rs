@@ -0,0 +1,402 @@
+use std::collections::{BTreeMap,HashSet};
+use std::ffi::OsString;
+use std::path::{Component,Path,PathBuf};
+use crate::archive::load_checksums;
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::compare::ChangeSet;
+
+use async_trait::async_trait;
+
+/// The name of the manifest `manifest split` writes files directly in the root to.
+const SPLIT_TOP_LEVEL: &str = "_top-level";
+
+/// The first component of `path` and the rest, `None` for files directly in the root.
+fn split_top_level( path: &Path ) -> Option< ( OsString, PathBuf ) > {
+    let mut components = path.components();
+    match components.next() {
+        Some( Component::Normal( top ) ) => {
+            let rest = components.as_path();
+            if rest.as_os_str().is_empty() {
+                None
+            } else {
+                Some( ( top.to_owned(), rest.to_owned() ) )
+            }
+        },
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+pub struct ManifestMerge {
+    /// The manifests to merge, each with the prefix its paths are placed below.
+    inputs: Vec< ( String, PathBuf ) >,
+    output_file: String,
+}
+
+impl ManifestMerge {
+    pub fn new( output_file: &str ) -> Self {
+        Self {
+            inputs: Vec::new(),
+            output_file: output_file.to_string(),
+        }
+    }
+
+    pub fn add_input( &mut self, checksum_file: &str, prefix: &Path ) {
+        self.inputs.push( ( checksum_file.to_string(), prefix.to_owned() ) );
+    }
+}
+
+#[async_trait]
+impl CommandAsync for ManifestMerge {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let mut merged: Option< Checksums > = None;
+        let mut sources: BTreeMap< PathBuf, &str > = BTreeMap::new();
+        for ( checksum_file, prefix ) in self.inputs.iter() {
+            let checksums = load_checksums( checksum_file )?;
+            let merged = merged.get_or_insert_with( || Checksums::new( checksums.algorithm() ) );
+            if merged.algorithm() != checksums.algorithm() {
+                anyhow::bail!( "{} uses {}, but {} is expected", checksum_file, checksums.algorithm(), merged.algorithm() );
+            }
+            for e in checksums.entries() {
+                let path = prefix.join( e.path() );
+                if let Some( other ) = sources.insert( path.clone(), checksum_file ) {
+                    anyhow::bail!( "{:?} is in both {} and {}", &path, other, checksum_file );
+                }
+                let mut e = e.clone();
+                e.set_path( &path );
+                merged.add( e );
+            }
+        }
+
+        let merged = match merged {
+            Some( merged ) => merged,
+            None => anyhow::bail!( "Nothing to merge" ),
+        };
+        merged.save( &self.output_file )?;
+        println!( "Merged {} manifests into {} with {} files.", self.inputs.len(), self.output_file, merged.len() );
+        Ok(())
+    }
+}
+
+/// Selects entries by their status compared to another manifest.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum FilterStatus {
+    Added,
+    Changed,
+    Unchanged,
+}
+
+impl FilterStatus {
+    pub fn from_name( name: &str ) -> Option< Self > {
+        match name {
+            "added" => Some( FilterStatus::Added ),
+            "changed" => Some( FilterStatus::Changed ),
+            "unchanged" => Some( FilterStatus::Unchanged ),
+            _ => None,
+        }
+    }
+}
+
+/// Selects paths by include and exclude glob patterns, everything is included without any.
+#[derive(Debug,Default)]
+pub struct PathFilter {
+    include: Vec< glob::Pattern >,
+    exclude: Vec< glob::Pattern >,
+}
+
+impl PathFilter {
+    pub fn add_include( &mut self, pattern: &str ) -> anyhow::Result< () > {
+        self.include.push( glob::Pattern::new( pattern )? );
+        Ok(())
+    }
+
+    pub fn add_exclude( &mut self, pattern: &str ) -> anyhow::Result< () > {
+        self.exclude.push( glob::Pattern::new( pattern )? );
+        Ok(())
+    }
+
+    pub fn is_empty( &self ) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn matches( &self, path: &Path ) -> bool {
+        ( self.include.is_empty() || self.include.iter().any( |p| p.matches_path( path ) ) )
+            && !self.exclude.iter().any( |p| p.matches_path( path ) )
+    }
+
+    /// The patterns, as recorded in the manifest header.
+    pub fn describe( &self ) -> ( String, String ) {
+        let join = |patterns: &[glob::Pattern]| patterns.iter().map( |p| p.as_str() ).collect::< Vec< _ > >().join( "," );
+        ( join( &self.include ), join( &self.exclude ) )
+    }
+
+    /// A copy of `checksums` with only the matching entries, with exact path matching.
+    pub fn apply( &self, checksums: &Checksums ) -> Checksums {
+        let mut filtered = Checksums::new( checksums.algorithm() );
+        *filtered.header_mut() = checksums.header().clone();
+        for e in checksums.entries().iter().filter( |e| self.matches( e.path() ) ) {
+            filtered.add( e.clone() );
+        }
+        filtered
+    }
+}
+
+#[derive(Debug)]
+pub struct ManifestFilter {
+    checksum_file: String,
+    output_file: String,
+    paths: PathFilter,
+    min_size: Option< u64 >,
+    max_size: Option< u64 >,
+    status: Option< ( FilterStatus, String ) >,
+}
+
+impl ManifestFilter {
+    pub fn new( checksum_file: &str, output_file: &str ) -> Self {
+        Self {
+            checksum_file: checksum_file.to_string(),
+            output_file: output_file.to_string(),
+            paths: PathFilter::default(),
+            min_size: None,
+            max_size: None,
+            status: None,
+        }
+    }
+
+    pub fn add_include( &mut self, pattern: &str ) -> anyhow::Result< () > {
+        self.paths.add_include( pattern )
+    }
+
+    pub fn add_exclude( &mut self, pattern: &str ) -> anyhow::Result< () > {
+        self.paths.add_exclude( pattern )
+    }
+
+    pub fn set_min_size( &mut self, min_size: u64 ) {
+        self.min_size = Some( min_size );
+    }
+
+    pub fn set_max_size( &mut self, max_size: u64 ) {
+        self.max_size = Some( max_size );
+    }
+
+    /// Only keeps entries with `status` compared to the manifest in `checksum_file_old`.
+    pub fn set_status( &mut self, status: FilterStatus, checksum_file_old: &str ) {
+        self.status = Some( ( status, checksum_file_old.to_string() ) );
+    }
+
+    fn matches( &self, entry: &ChecksumsEntry ) -> bool {
+        self.paths.matches( entry.path() )
+            && self.min_size.is_none_or( |s| entry.size() >= s )
+            && self.max_size.is_none_or( |s| entry.size() <= s )
+    }
+}
+
+#[async_trait]
+impl CommandAsync for ManifestFilter {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let checksums = load_checksums( &self.checksum_file )?;
+
+        let selected = match &self.status {
+            Some( ( status, checksum_file_old ) ) => {
+                let old_checksums = load_checksums( checksum_file_old )?;
+                let change_set = ChangeSet::new( &old_checksums, &checksums, None );
+                let paths = match status {
+                    FilterStatus::Added => change_set.added,
+                    FilterStatus::Changed => change_set.changed,
+                    FilterStatus::Unchanged => change_set.unchanged,
+                };
+                Some( paths.into_iter().collect::< HashSet< PathBuf > >() )
+            },
+            None => None,
+        };
+
+        let mut filtered = Checksums::new( checksums.algorithm() );
+        *filtered.header_mut() = checksums.header().clone();
+        for e in checksums.entries() {
+            if !self.matches( e ) {
+                continue;
+            }
+            if let Some( selected ) = &selected {
+                if !selected.contains( e.path() ) {
+                    continue;
+                }
+            }
+            filtered.add( e.clone() );
+        }
+
+        filtered.save( &self.output_file )?;
+        println!( "Kept {} of {} files.", filtered.len(), checksums.len() );
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ManifestRebase {
+    checksum_file: String,
+    output_file: String,
+    strip_prefix: Option< PathBuf >,
+    add_prefix: Option< PathBuf >,
+}
+
+impl ManifestRebase {
+    pub fn new( checksum_file: &str, output_file: &str ) -> Self {
+        Self {
+            checksum_file: checksum_file.to_string(),
+            output_file: output_file.to_string(),
+            strip_prefix: None,
+            add_prefix: None,
+        }
+    }
+
+    pub fn set_strip_prefix( &mut self, strip_prefix: &Path ) {
+        self.strip_prefix = Some( strip_prefix.to_owned() );
+    }
+
+    pub fn set_add_prefix( &mut self, add_prefix: &Path ) {
+        self.add_prefix = Some( add_prefix.to_owned() );
+    }
+}
+
+#[async_trait]
+impl CommandAsync for ManifestRebase {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let checksums = load_checksums( &self.checksum_file )?;
+        let mut rebased = Checksums::new( checksums.algorithm() );
+        *rebased.header_mut() = checksums.header().clone();
+
+        let mut outside = 0;
+        for e in checksums.entries() {
+            let mut path = e.path().to_owned();
+            if let Some( strip_prefix ) = &self.strip_prefix {
+                match path.strip_prefix( strip_prefix ) {
+                    Ok( p ) => path = p.to_owned(),
+                    Err( _ ) => {
+                        outside += 1;
+                        continue;
+                    },
+                }
+            }
+            if let Some( add_prefix ) = &self.add_prefix {
+                path = add_prefix.join( path );
+            }
+            let mut e = e.clone();
+            e.set_path( &path );
+            rebased.add( e );
+        }
+
+        if outside > 0 {
+            println!( "WARNING: Dropped {} files outside of {:?}", outside, self.strip_prefix.as_ref().unwrap_or( &PathBuf::new() ) );
+        }
+        rebased.save( &self.output_file )?;
+        println!( "Rebased {} files.", rebased.len() );
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ManifestSplit {
+    checksum_file: String,
+    output_dir: PathBuf,
+}
+
+impl ManifestSplit {
+    pub fn new( checksum_file: &str, output_dir: &Path ) -> Self {
+        Self {
+            checksum_file: checksum_file.to_string(),
+            output_dir: output_dir.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandAsync for ManifestSplit {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let checksums = load_checksums( &self.checksum_file )?;
+
+        // paths in the split manifests are relative to their top level directory
+        let mut parts: BTreeMap< OsString, Checksums > = BTreeMap::new();
+        for e in checksums.entries() {
+            let ( name, path ) = match split_top_level( e.path() ) {
+                Some( ( top, rest ) ) if top != SPLIT_TOP_LEVEL => ( top, rest ),
+                Some( _ ) => anyhow::bail!( "Can not split a directory named {}", SPLIT_TOP_LEVEL ),
+                None => ( OsString::from( SPLIT_TOP_LEVEL ), e.path().to_owned() ),
+            };
+            let part = parts.entry( name ).or_insert_with( || {
+                let mut part = Checksums::new( checksums.algorithm() );
+                *part.header_mut() = checksums.header().clone();
+                part
+            });
+            let mut e = e.clone();
+            e.set_path( &path );
+            part.add( e );
+        }
+
+        // names that are not UTF-8 could end up in the same file, check before anything is written
+        let mut filenames: BTreeMap< String, &OsString > = BTreeMap::new();
+        for name in parts.keys() {
+            let filename = self.output_dir.join( format!( "{}.json", name.to_string_lossy() ) ).to_string_lossy().into_owned();
+            if let Some( other ) = filenames.insert( filename.clone(), name ) {
+                anyhow::bail!( "Top level directories {:?} and {:?} would both be split into {}", other, name, filename );
+            }
+        }
+
+        std::fs::create_dir_all( &self.output_dir )?;
+        for ( filename, name ) in filenames.iter() {
+            let part = &parts[ *name ];
+            part.save( filename )?;
+            println!( "{:>8} files in {}", part.len(), filename );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct ManifestStats {
+    checksum_file: String,
+}
+
+impl ManifestStats {
+    pub fn new( checksum_file: &str ) -> Self {
+        Self {
+            checksum_file: checksum_file.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl CommandAsync for ManifestStats {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let checksums = load_checksums( &self.checksum_file )?;
+        let entries = checksums.entries();
+
+        println!( "{} {}", self.checksum_file, checksums.header().describe() );
+        for ( name, value ) in checksums.header().options() {
+            println!( "    {} = {}", name, value );
+        }
+        println!( "Algorithm:       {}", checksums.algorithm() );
+        println!( "Files:           {}", checksums.len() );
+        println!( "Total size:      {} bytes", checksums.total_size() );
+        println!( "Without hash:    {}", entries.iter().filter( |e| !e.has_hash() ).count() );
+
+        let mut hashes: Vec< &str > = entries.iter().filter( |e| e.has_hash() ).map( |e| e.hash() ).collect();
+        hashes.sort_unstable();
+        hashes.dedup();
+        println!( "Distinct hashes: {}", hashes.len() );
+        if let Some( largest ) = entries.iter().max_by_key( |e| e.size() ) {
+            println!( "Largest file:    {} ({} bytes)", largest.path().to_string_lossy(), largest.size() );
+        }
+
+        let mut top_level: BTreeMap< OsString, ( usize, u64 ) > = BTreeMap::new();
+        for e in entries {
+            let name = split_top_level( e.path() ).map( |( top, _ )| top ).unwrap_or_else( || OsString::from( "." ) );
+            let t = top_level.entry( name ).or_default();
+            t.0 += 1;
+            t.1 += e.size();
+        }
+        for ( name, ( files, size ) ) in top_level.iter() {
+            println!( "{:>8} files {:>14} bytes in {}", files, size, name.to_string_lossy() );
+        }
+        Ok(())
+    }
+}
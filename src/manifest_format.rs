@@ -0,0 +1,620 @@
+use std::io::{BufRead,BufReader,Read,Write};
+use std::path::{Path,PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::binary_manifest::{BinaryFormat,BINARY_MAGIC};
+use crate::bundle::is_zstd;
+use crate::checksums::*;
+use crate::portable_path;
+use crate::sqlite_manifest::{SqliteFormat,SQLITE_MAGIC};
+
+/// Parses a manifest from the (uncompressed) content of a file.
+pub trait ManifestReader {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums >;
+}
+
+pub trait ManifestWriter {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () >;
+}
+
+/// The formats manifests are read and written in, the native one and those of other tools.
+///
+/// Only the native JSON format keeps everything, the other formats lose the header
+/// and, depending on the format, sizes and modification times.
+/// Every format can additionally be compressed.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ManifestFormat {
+    Json,
+    Ndjson,
+    Csv,
+    Sum,
+    Hashdeep,
+    Sfv,
+    Binary,
+    Sqlite,
+}
+
+const HASHDEEP_MAGIC: &[u8] = b"%%%% HASHDEEP";
+const GZIP_MAGIC: [u8; 2] = [ 0x1f, 0x8b ];
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_name( name: &str ) -> Option< Self > {
+        match name {
+            "none" => Some( Compression::None ),
+            "gzip" => Some( Compression::Gzip ),
+            "zstd" => Some( Compression::Zstd ),
+            _ => None,
+        }
+    }
+
+    pub fn from_path( path: &Path ) -> Self {
+        match path.extension().map( |e| e.to_string_lossy().to_lowercase() ).as_deref() {
+            Some( "gz" ) => Compression::Gzip,
+            Some( "zst" ) => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Detects the compression by the magic bytes at the start of `data`.
+    pub fn detect( data: &[u8] ) -> Self {
+        if data.starts_with( &GZIP_MAGIC ) {
+            Compression::Gzip
+        } else if is_zstd( data ) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Reads the decompressed content from `r`, as it is needed.
+    pub fn decoder< R: BufRead + 'static >( &self, r: R ) -> anyhow::Result< Box< dyn Read > > {
+        Ok( match self {
+            Compression::None => Box::new( r ),
+            Compression::Gzip => Box::new( flate2::bufread::MultiGzDecoder::new( r ) ),
+            Compression::Zstd => Box::new( zstd::Decoder::with_buffer( r )? ),
+        })
+    }
+
+    /// Writes to `w` through the compression, `write` has to write everything.
+    pub fn write_with< W: Write >( &self, w: W, write: impl FnOnce( &mut dyn Write ) -> anyhow::Result< () > ) -> anyhow::Result< W > {
+        match self {
+            Compression::None => {
+                let mut w = w;
+                write( &mut w )?;
+                Ok( w )
+            },
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new( w, flate2::Compression::default() );
+                write( &mut encoder )?;
+                Ok( encoder.finish()? )
+            },
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new( w, 0 )?;
+                write( &mut encoder )?;
+                Ok( encoder.finish()? )
+            },
+        }
+    }
+}
+
+/// `path` without a compression extension.
+fn uncompressed_path( path: &Path ) -> &Path {
+    match Compression::from_path( path ) {
+        Compression::None => path,
+        _ => path.file_stem().map( Path::new ).unwrap_or( path ),
+    }
+}
+
+impl ManifestFormat {
+    pub fn from_name( name: &str ) -> Option< Self > {
+        match name {
+            "json" => Some( ManifestFormat::Json ),
+            "ndjson" => Some( ManifestFormat::Ndjson ),
+            "csv" => Some( ManifestFormat::Csv ),
+            "sum" => Some( ManifestFormat::Sum ),
+            "hashdeep" => Some( ManifestFormat::Hashdeep ),
+            "sfv" => Some( ManifestFormat::Sfv ),
+            "binary" => Some( ManifestFormat::Binary ),
+            "sqlite" => Some( ManifestFormat::Sqlite ),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the extension, everything unknown is native JSON.
+    pub fn from_path( path: &Path ) -> Self {
+        let extension = uncompressed_path( path ).extension().map( |e| e.to_string_lossy().to_lowercase() ).unwrap_or_default();
+        match extension.as_str() {
+            "ndjson" | "jsonl" => ManifestFormat::Ndjson,
+            "csv" => ManifestFormat::Csv,
+            "md5" | "sha1" | "sha256" | "sha512" | "md5sum" | "sha1sum" | "sha256sum" | "sha512sum" => ManifestFormat::Sum,
+            "hashdeep" => ManifestFormat::Hashdeep,
+            "sfv" => ManifestFormat::Sfv,
+            "fcm" => ManifestFormat::Binary,
+            "sqlite" | "sqlite3" => ManifestFormat::Sqlite,
+            _ => ManifestFormat::Json,
+        }
+    }
+
+    /// Like `from_path`, but trusts the content where it is unambiguous.
+    pub fn detect( path: &Path, data: &[u8] ) -> Self {
+        if data.starts_with( HASHDEEP_MAGIC ) {
+            ManifestFormat::Hashdeep
+        } else if data.starts_with( BINARY_MAGIC ) {
+            ManifestFormat::Binary
+        } else if data.starts_with( SQLITE_MAGIC ) {
+            ManifestFormat::Sqlite
+        } else {
+            ManifestFormat::from_path( path )
+        }
+    }
+
+    pub fn reader( &self ) -> Box< dyn ManifestReader > {
+        match self {
+            ManifestFormat::Json => Box::new( JsonFormat ),
+            ManifestFormat::Ndjson => Box::new( NdjsonFormat ),
+            ManifestFormat::Csv => Box::new( CsvFormat ),
+            ManifestFormat::Sum => Box::new( SumFormat ),
+            ManifestFormat::Hashdeep => Box::new( HashdeepFormat ),
+            ManifestFormat::Sfv => Box::new( SfvFormat ),
+            ManifestFormat::Binary => Box::new( BinaryFormat ),
+            ManifestFormat::Sqlite => Box::new( SqliteFormat ),
+        }
+    }
+
+    pub fn writer( &self ) -> Box< dyn ManifestWriter > {
+        match self {
+            ManifestFormat::Json => Box::new( JsonFormat ),
+            ManifestFormat::Ndjson => Box::new( NdjsonFormat ),
+            ManifestFormat::Csv => Box::new( CsvFormat ),
+            ManifestFormat::Sum => Box::new( SumFormat ),
+            ManifestFormat::Hashdeep => Box::new( HashdeepFormat ),
+            ManifestFormat::Sfv => Box::new( SfvFormat ),
+            ManifestFormat::Binary => Box::new( BinaryFormat ),
+            ManifestFormat::Sqlite => Box::new( SqliteFormat ),
+        }
+    }
+}
+
+/// Foreign formats do not name the algorithm, so it is guessed from the length of the hashes.
+fn algorithm_for_hash( hash: &str ) -> anyhow::Result< &'static str > {
+    match hash.len() {
+        8 => Ok( "crc32" ),
+        32 => Ok( "md5" ),
+        40 => Ok( "sha1" ),
+        64 => Ok( "sha256" ),
+        128 => Ok( "sha512" ),
+        _ => anyhow::bail!( "Can not tell the algorithm of hash {:?}", hash ),
+    }
+}
+
+/// The non empty lines of `r`, without line endings.
+fn lines( r: &mut dyn Read ) -> impl Iterator< Item = std::io::Result< Vec< u8 > > > + '_ {
+    BufReader::new( r ).split( b'\n' )
+        .map( |l| l.map( |mut l| {
+            if l.ends_with( b"\r" ) {
+                l.pop();
+            }
+            l
+        }))
+        .filter( |l| l.as_ref().map_or( true, |l| !l.is_empty() ) )
+}
+
+/// Collects entries of a foreign format, which all have to agree on the algorithm.
+struct ForeignManifest {
+    checksums: Option< Checksums >,
+    has_sizes: bool,
+}
+
+impl ForeignManifest {
+    fn new( has_sizes: bool ) -> Self {
+        Self {
+            checksums: None,
+            has_sizes,
+        }
+    }
+
+    fn add( &mut self, algorithm: &str, path: PathBuf, size: u64, hash: &str ) -> anyhow::Result< () > {
+        let has_sizes = self.has_sizes;
+        let checksums = self.checksums.get_or_insert_with( || {
+            let mut checksums = Checksums::new( algorithm );
+            *checksums.header_mut() = ManifestHeader::unknown_origin();
+            if !has_sizes {
+                checksums.header_mut().set_option( SIZES_OPTION, SIZES_UNKNOWN );
+            }
+            checksums
+        });
+        if checksums.algorithm() != algorithm {
+            anyhow::bail!( "Mixed algorithms {} and {}", checksums.algorithm(), algorithm );
+        }
+        checksums.add( ChecksumsEntry::new( &path, size, &hash.to_uppercase() ) );
+        Ok(())
+    }
+
+    fn finish( self, default_algorithm: &str ) -> Checksums {
+        self.checksums.unwrap_or_else( || Checksums::new( default_algorithm ) )
+    }
+}
+
+/// The native format, see `Checksums`.
+pub struct JsonFormat;
+
+impl ManifestReader for JsonFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let checksums: Checksums = serde_json::from_reader( BufReader::new( r ) )?;
+        match checksums.header().format_version() {
+            // no header yet, everything else is unchanged
+            0 | FORMAT_VERSION => {},
+            v => anyhow::bail!( "Format version {} is not supported, only {} and older", v, FORMAT_VERSION ),
+        }
+        Ok( checksums )
+    }
+}
+
+impl ManifestWriter for JsonFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        // canonical manifests end up in version control, pretty printed a change only touches the lines of its entry
+        if checksums.is_canonical() {
+            serde_json::to_writer_pretty( &mut *w, checksums )?;
+            w.write_all( b"\n" )?;
+        } else {
+            serde_json::to_writer( w, checksums )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize,Serialize)]
+struct NdjsonHeader {
+    header: ManifestHeader,
+    algorithm: String,
+}
+
+/// The header and algorithm on the first line, followed by one entry per line.
+pub struct NdjsonFormat;
+
+impl ManifestReader for NdjsonFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut lines = lines( r );
+        let first = match lines.next() {
+            Some( first ) => first?,
+            None => anyhow::bail!( "Empty manifest" ),
+        };
+        let NdjsonHeader { header, algorithm } = serde_json::from_slice( &first )?;
+        let mut checksums = Checksums::new( &algorithm );
+        *checksums.header_mut() = header;
+        for l in lines {
+            checksums.add( serde_json::from_slice( &l? )? );
+        }
+        Ok( checksums )
+    }
+}
+
+impl ManifestWriter for NdjsonFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        let header = NdjsonHeader {
+            header: checksums.saved_header(),
+            algorithm: checksums.algorithm().to_string(),
+        };
+        serde_json::to_writer( &mut *w, &header )?;
+        w.write_all( b"\n" )?;
+        for e in checksums.saved_entries() {
+            serde_json::to_writer( &mut *w, &e )?;
+            w.write_all( b"\n" )?;
+        }
+        Ok(())
+    }
+}
+
+/// `path,size,<algorithm>,mtime` with a header row, paths are portable encoded.
+pub struct CsvFormat;
+
+impl ManifestReader for CsvFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut r = csv::Reader::from_reader( r );
+        let algorithm = match r.headers()?.get( 2 ) {
+            Some( algorithm ) => algorithm.to_string(),
+            None => anyhow::bail!( "Missing hash column" ),
+        };
+        let mut checksums = Checksums::new( &algorithm );
+        *checksums.header_mut() = ManifestHeader::unknown_origin();
+        for record in r.records() {
+            let record = record?;
+            let path = portable_path::decode( record.get( 0 ).unwrap_or_default() )?;
+            let size = record.get( 1 ).unwrap_or_default().parse()?;
+            let mut e = ChecksumsEntry::new( &path, size, record.get( 2 ).unwrap_or_default() );
+            if let Some( mtime ) = record.get( 3 ).filter( |m| !m.is_empty() ) {
+                e.set_mtime( mtime.parse()? );
+            }
+            checksums.add( e );
+        }
+        Ok( checksums )
+    }
+}
+
+impl ManifestWriter for CsvFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        let mut w = csv::Writer::from_writer( w );
+        w.write_record( [ "path", "size", checksums.algorithm(), "mtime" ] )?;
+        for e in checksums.saved_entries() {
+            let mtime = e.mtime().map( |m| m.to_string() ).unwrap_or_default();
+            w.write_record( [ &portable_path::encode( e.path() ), &e.size().to_string(), e.hash(), &mtime ] )?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// The output of coreutils `md5sum`, `sha1sum`, ... without sizes.
+pub struct SumFormat;
+
+impl ManifestReader for SumFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut manifest = ForeignManifest::new( false );
+        for l in lines( r ) {
+            let line = l?;
+            let l = line.as_slice();
+            // names with a backslash or a newline are escaped, which is marked by a leading backslash
+            let ( escaped, l ) = match l.strip_prefix( b"\\" ) {
+                Some( l ) => ( true, l ),
+                None => ( false, l ),
+            };
+            let split = match l.iter().position( |b| *b == b' ' ) {
+                Some( split ) if l.len() > split + 1 => split,
+                _ => anyhow::bail!( "Invalid line {:?}", String::from_utf8_lossy( l ) ),
+            };
+            let hash = std::str::from_utf8( &l[ ..split ] )?;
+            // the second separator is ' ' for text, and '*' for binary mode
+            let name = &l[ split+2.. ];
+            let name = if escaped { unescape_sum_name( name ) } else { name.to_vec() };
+            manifest.add( algorithm_for_hash( hash )?, portable_path::from_bytes( &name ), 0, hash )?;
+        }
+        Ok( manifest.finish( "sha1" ) )
+    }
+}
+
+fn unescape_sum_name( name: &[u8] ) -> Vec< u8 > {
+    let mut unescaped = Vec::with_capacity( name.len() );
+    let mut i = name.iter();
+    while let Some( b ) = i.next() {
+        match ( b, i.clone().next() ) {
+            ( b'\\', Some( b'n' ) ) => { unescaped.push( b'\n' ); i.next(); },
+            ( b'\\', Some( b'r' ) ) => { unescaped.push( b'\r' ); i.next(); },
+            ( b'\\', Some( b'\\' ) ) => { unescaped.push( b'\\' ); i.next(); },
+            ( b, _ ) => unescaped.push( *b ),
+        }
+    }
+    unescaped
+}
+
+impl ManifestWriter for SumFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        for e in checksums.saved_entries() {
+            let name = portable_path::to_bytes( e.path() );
+            let escape = name.iter().any( |b| matches!( b, b'\\' | b'\n' | b'\r' ) );
+            if escape {
+                w.write_all( b"\\" )?;
+            }
+            w.write_all( format!( "{}  ", e.hash().to_lowercase() ).as_bytes() )?;
+            if escape {
+                for b in name {
+                    match b {
+                        b'\\' => w.write_all( b"\\\\" )?,
+                        b'\n' => w.write_all( b"\\n" )?,
+                        b'\r' => w.write_all( b"\\r" )?,
+                        b => w.write_all( &[ b ] )?,
+                    }
+                }
+            } else {
+                w.write_all( &name )?;
+            }
+            w.write_all( b"\n" )?;
+        }
+        Ok(())
+    }
+}
+
+/// The csv like output of hashdeep and md5deep `-c`.
+pub struct HashdeepFormat;
+
+impl ManifestReader for HashdeepFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut manifest = ForeignManifest::new( true );
+        let mut columns: Vec< String > = Vec::new();
+        for l in lines( r ) {
+            let line = l?;
+            let l = line.as_slice();
+            if let Some( format ) = l.strip_prefix( b"%%%% size," ) {
+                columns = String::from_utf8_lossy( format ).split( ',' ).map( |c| c.to_string() ).collect();
+                continue;
+            }
+            if l.starts_with( b"%%%%" ) || l.starts_with( b"#" ) {
+                continue;
+            }
+            if columns.last().map( |c| c.as_str() ) != Some( "filename" ) {
+                anyhow::bail!( "Missing or invalid hashdeep header" );
+            }
+            // the filename is last, and may contain commas itself
+            let fields: Vec< &[u8] > = l.splitn( columns.len() + 1, |b| *b == b',' ).collect();
+            if fields.len() != columns.len() + 1 {
+                anyhow::bail!( "Invalid line {:?}", String::from_utf8_lossy( l ) );
+            }
+            let size = std::str::from_utf8( fields[ 0 ] )?.parse()?;
+            // prefer the hash we can calculate ourselves
+            let column = columns.iter().position( |c| c == "sha1" ).unwrap_or( 0 );
+            let hash = std::str::from_utf8( fields[ column + 1 ] )?;
+            let path = portable_path::from_bytes( fields[ columns.len() ] );
+            manifest.add( &columns[ column ], path, size, hash )?;
+        }
+        Ok( manifest.finish( "sha1" ) )
+    }
+}
+
+impl ManifestWriter for HashdeepFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        w.write_all( b"%%%% HASHDEEP-1.0\n" )?;
+        w.write_all( format!( "%%%% size,{},filename\n", checksums.algorithm() ).as_bytes() )?;
+        w.write_all( format!( "## Written by folder-compare-rs {}\n##\n", env!( "CARGO_PKG_VERSION" ) ).as_bytes() )?;
+        for e in checksums.saved_entries() {
+            w.write_all( format!( "{},{},", e.size(), e.hash().to_lowercase() ).as_bytes() )?;
+            w.write_all( &portable_path::to_bytes( e.path() ) )?;
+            w.write_all( b"\n" )?;
+        }
+        Ok(())
+    }
+}
+
+/// Simple file verification, a crc32 after every name.
+pub struct SfvFormat;
+
+impl ManifestReader for SfvFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut manifest = ForeignManifest::new( false );
+        for l in lines( r ) {
+            let line = l?;
+            let l = line.as_slice();
+            if l.starts_with( b";" ) {
+                continue;
+            }
+            let split = match l.iter().rposition( |b| *b == b' ' ) {
+                Some( split ) => split,
+                None => anyhow::bail!( "Invalid line {:?}", String::from_utf8_lossy( l ) ),
+            };
+            let hash = std::str::from_utf8( &l[ split+1.. ] )?;
+            let name = l[ ..split ].strip_suffix( b" " ).unwrap_or( &l[ ..split ] );
+            manifest.add( "crc32", portable_path::from_bytes( name ), 0, hash )?;
+        }
+        Ok( manifest.finish( "crc32" ) )
+    }
+}
+
+impl ManifestWriter for SfvFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        if checksums.algorithm() != "crc32" {
+            anyhow::bail!( "SFV can only hold crc32, not {}", checksums.algorithm() );
+        }
+        w.write_all( format!( "; Written by folder-compare-rs {}\n", env!( "CARGO_PKG_VERSION" ) ).as_bytes() )?;
+        for e in checksums.saved_entries() {
+            w.write_all( &portable_path::to_bytes( e.path() ) )?;
+            w.write_all( format!( " {}\n", e.hash() ).as_bytes() )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample( algorithm: &str, hash: &str ) -> Checksums {
+        let mut checksums = Checksums::new( algorithm );
+        let mut e = ChecksumsEntry::new( Path::new( "dir/a, b.txt" ), 12, hash );
+        e.set_mtime( 1600000000 );
+        checksums.add( e );
+        checksums.add( ChecksumsEntry::new( Path::new( "back\\slash" ), 0, hash ) );
+        checksums
+    }
+
+    fn parse( format: ManifestFormat, data: &[u8] ) -> anyhow::Result< Checksums > {
+        format.reader().read( &mut &*data )
+    }
+
+    fn round_trip( format: ManifestFormat, checksums: &Checksums ) -> Checksums {
+        let mut data = Vec::new();
+        format.writer().write( checksums, &mut data ).unwrap();
+        parse( format, &data ).unwrap()
+    }
+
+    fn paths( checksums: &Checksums ) -> Vec< ( PathBuf, String ) > {
+        let mut paths: Vec< ( PathBuf, String ) > = checksums.entries().iter().map( |e| ( e.path().to_owned(), e.hash().to_string() ) ).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn text_formats_round_trip() {
+        let sha1 = "A9993E364706816ABA3E25717850C26C9CD0D89D";
+        let checksums = sample( "sha1", sha1 );
+        for format in [ ManifestFormat::Json, ManifestFormat::Ndjson, ManifestFormat::Csv, ManifestFormat::Sum, ManifestFormat::Hashdeep ].iter() {
+            let read = round_trip( *format, &checksums );
+            assert_eq!( read.algorithm(), "sha1", "{:?}", format );
+            assert_eq!( paths( &read ), paths( &checksums ), "{:?}", format );
+        }
+        // only sum escapes line breaks
+        let mut newline = Checksums::new( "sha1" );
+        newline.add( ChecksumsEntry::new( Path::new( "new\nline" ), 0, sha1 ) );
+        assert_eq!( paths( &round_trip( ManifestFormat::Sum, &newline ) ), paths( &newline ) );
+
+        let crc32 = sample( "crc32", "352441C2" );
+        assert_eq!( paths( &round_trip( ManifestFormat::Sfv, &crc32 ) ), paths( &crc32 ) );
+    }
+
+    #[test]
+    fn sizes_and_mtimes_survive_where_stored() {
+        let checksums = sample( "sha1", "A9993E364706816ABA3E25717850C26C9CD0D89D" );
+        for format in [ ManifestFormat::Json, ManifestFormat::Ndjson, ManifestFormat::Csv ].iter() {
+            let read = round_trip( *format, &checksums );
+            let e = read.find( Path::new( "dir/a, b.txt" ) ).unwrap();
+            assert_eq!( e.size(), 12, "{:?}", format );
+            assert_eq!( e.mtime(), Some( 1600000000 ), "{:?}", format );
+        }
+        let read = round_trip( ManifestFormat::Sum, &checksums );
+        assert!( !read.has_sizes() );
+        assert!( round_trip( ManifestFormat::Hashdeep, &checksums ).has_sizes() );
+    }
+
+    #[test]
+    fn json_without_header_is_version_0() {
+        let read = parse( ManifestFormat::Json, br#"{"algorithm":"sha1","entries":[{"path":"x","size":1,"hash":"AB"}],"total_size":1}"# ).unwrap();
+        assert_eq!( read.header().format_version(), 0 );
+        assert_eq!( read.header().describe(), "unknown origin" );
+        assert_eq!( read.len(), 1 );
+        // saving writes the current format
+        assert_eq!( round_trip( ManifestFormat::Json, &read ).header().format_version(), FORMAT_VERSION );
+
+        let newer = br#"{"header":{"format_version":99,"options":{}},"algorithm":"sha1","entries":[],"total_size":0}"#;
+        assert!( parse( ManifestFormat::Json, newer ).is_err() );
+    }
+
+    #[test]
+    fn sum_reads_coreutils_output() {
+        let data = b"a9993e364706816aba3e25717850c26c9cd0d89d  text.txt\r\n\\a9993e364706816aba3e25717850c26c9cd0d89d *bin\\nary\\\\.dat\n\n";
+        let read = parse( ManifestFormat::Sum, data ).unwrap();
+        assert_eq!( read.algorithm(), "sha1" );
+        assert_eq!( paths( &read ), vec![
+            ( PathBuf::from( "bin\nary\\.dat" ), "A9993E364706816ABA3E25717850C26C9CD0D89D".to_string() ),
+            ( PathBuf::from( "text.txt" ), "A9993E364706816ABA3E25717850C26C9CD0D89D".to_string() ),
+        ] );
+        assert!( parse( ManifestFormat::Sum, b"d41d8cd98f00b204e9800998ecf8427e  a\na9993e364706816aba3e25717850c26c9cd0d89d  b\n" ).is_err() );
+        assert!( parse( ManifestFormat::Sum, b"nohash\n" ).is_err() );
+    }
+
+    #[test]
+    fn hashdeep_prefers_sha1() {
+        let data = b"%%%% HASHDEEP-1.0\n%%%% size,md5,sha1,filename\n## comment\n5,d41d8cd98f00b204e9800998ecf8427e,a9993e364706816aba3e25717850c26c9cd0d89d,dir/a,b.txt\n";
+        let read = parse( ManifestFormat::Hashdeep, data ).unwrap();
+        assert_eq!( read.algorithm(), "sha1" );
+        assert_eq!( read.entries()[ 0 ].size(), 5 );
+        assert_eq!( paths( &read ), vec![ ( PathBuf::from( "dir/a,b.txt" ), "A9993E364706816ABA3E25717850C26C9CD0D89D".to_string() ) ] );
+        assert!( parse( ManifestFormat::Hashdeep, b"5,abc,file\n" ).is_err() );
+    }
+
+    #[test]
+    fn sfv_reads_names_with_spaces() {
+        let read = parse( ManifestFormat::Sfv, b"; comment\nsome file.bin 352441c2\n" ).unwrap();
+        assert_eq!( read.algorithm(), "crc32" );
+        assert_eq!( paths( &read ), vec![ ( PathBuf::from( "some file.bin" ), "352441C2".to_string() ) ] );
+        assert!( SfvFormat.write( &sample( "sha1", "A9993E364706816ABA3E25717850C26C9CD0D89D" ), &mut Vec::new() ).is_err() );
+    }
+
+    #[test]
+    fn empty_foreign_manifests_are_empty() {
+        assert_eq!( parse( ManifestFormat::Sum, b"" ).unwrap().len(), 0 );
+        assert!( parse( ManifestFormat::Ndjson, b"" ).is_err() );
+    }
+}
@@ -1,9 +1,12 @@
+use std::path::PathBuf;
+
 #[derive(Debug)]
 pub enum Message {
 	Started(u64, u64),
 	Progress(usize),
-	FileDone,
+	FileStarted(PathBuf, u64),
+	FileDone(PathBuf, u64),
     Success,
-    Failure,
+    Failure(PathBuf, String),
     Done,
 }
@@ -0,0 +1,68 @@
+use std::path::{Path,PathBuf};
+use unicode_normalization::UnicodeNormalization;
+use crate::portable_path;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum UnicodeForm {
+    Nfc,
+    Nfd,
+}
+
+impl UnicodeForm {
+    pub fn from_name( name: &str ) -> Option< Self > {
+        match name {
+            "nfc" => Some( UnicodeForm::Nfc ),
+            "nfd" => Some( UnicodeForm::Nfd ),
+            _ => None,
+        }
+    }
+}
+
+/// Decides which paths are considered the same when looking up entries,
+/// e.g. for trees that went through macOS (NFD) or case insensitive shares.
+#[derive(Debug,Clone,Copy,Default,PartialEq)]
+pub struct PathMatching {
+    normalize_unicode: Option< UnicodeForm >,
+    case_insensitive: bool,
+}
+
+impl PathMatching {
+    pub fn set_normalize_unicode( &mut self, normalize_unicode: UnicodeForm ) {
+        self.normalize_unicode = Some( normalize_unicode );
+    }
+    pub fn set_case_insensitive( &mut self, case_insensitive: bool ) {
+        self.case_insensitive = case_insensitive;
+    }
+
+    /// Exact matching, paths are used as they are.
+    pub fn is_exact( &self ) -> bool {
+        *self == PathMatching::default()
+    }
+
+    fn normalize( &self, s: String ) -> String {
+        match self.normalize_unicode {
+            Some( UnicodeForm::Nfc ) => s.nfc().collect(),
+            Some( UnicodeForm::Nfd ) => s.nfd().collect(),
+            None => s,
+        }
+    }
+
+    /// The path all paths considered the same map to.
+    /// Paths that are not valid unicode are only ever matched exactly.
+    pub fn key( &self, path: &Path ) -> PathBuf {
+        if self.is_exact() {
+            return path.to_owned();
+        }
+        let mut key = match String::from_utf8( portable_path::to_bytes( path ) ) {
+            Ok( key ) => key,
+            Err( e ) => return portable_path::from_bytes( e.as_bytes() ),
+        };
+        if self.case_insensitive {
+            // lowercasing can produce sequences that are no longer normalized
+            key = self.normalize( self.normalize( key ).to_lowercase() );
+        } else {
+            key = self.normalize( key );
+        }
+        portable_path::from_bytes( key.as_bytes() )
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::{Component,Path,PathBuf};
+use serde::{Deserialize, Deserializer, Serializer};
+
+const BASE64_PREFIX: &str = "b64:";
+
+#[cfg(unix)]
+fn component_bytes( c: &std::ffi::OsStr ) -> Vec< u8 > {
+    use std::os::unix::ffi::OsStrExt;
+    c.as_bytes().to_vec()
+}
+
+/// Windows names are UTF-16 that may contain unpaired surrogates, so they are stored as WTF-8.
+#[cfg(windows)]
+fn component_bytes( c: &std::ffi::OsStr ) -> Vec< u8 > {
+    use std::os::windows::ffi::OsStrExt;
+    wide_to_wtf8( &c.encode_wide().collect::< Vec< u16 > >() )
+}
+
+// :TODO: names that are not valid unicode can not be represented on other platforms yet
+#[cfg(not(any(unix,windows)))]
+fn component_bytes( c: &std::ffi::OsStr ) -> Vec< u8 > {
+    c.to_string_lossy().as_bytes().to_vec()
+}
+
+/// UTF-8, extended to encode unpaired surrogates like any other code point.
+#[cfg(any(windows,test))]
+fn wide_to_wtf8( wide: &[u16] ) -> Vec< u8 > {
+    let mut bytes = Vec::with_capacity( wide.len() );
+    for c in std::char::decode_utf16( wide.iter().copied() ) {
+        match c {
+            Ok( c ) => bytes.extend( c.encode_utf8( &mut [0; 4] ).as_bytes() ),
+            Err( e ) => {
+                let u = e.unpaired_surrogate();
+                bytes.extend( &[ 0xE0 | ( u >> 12 ) as u8, 0x80 | ( ( u >> 6 ) & 0x3F ) as u8, 0x80 | ( u & 0x3F ) as u8 ] );
+            },
+        }
+    }
+    bytes
+}
+
+/// The reverse of `wide_to_wtf8`, bytes that are not WTF-8 become replacement characters.
+#[cfg(any(windows,test))]
+fn wtf8_to_wide( bytes: &[u8] ) -> Vec< u16 > {
+    let mut wide = Vec::with_capacity( bytes.len() );
+    let mut i = 0;
+    while i < bytes.len() {
+        let ( len, first ) = match bytes[ i ] {
+            b @ 0x00..=0x7F => ( 1, b as u32 ),
+            b @ 0xC0..=0xDF => ( 2, ( b & 0x1F ) as u32 ),
+            b @ 0xE0..=0xEF => ( 3, ( b & 0x0F ) as u32 ),
+            b @ 0xF0..=0xF7 => ( 4, ( b & 0x07 ) as u32 ),
+            _ => ( 1, 0xFFFD ),
+        };
+        let continuation = bytes.get( i + 1..i + len ).filter( |c| c.iter().all( |b| b & 0xC0 == 0x80 ) );
+        let code_point = match continuation {
+            Some( c ) if len > 1 => c.iter().fold( first, |cp, b| ( cp << 6 ) | ( b & 0x3F ) as u32 ),
+            Some( _ ) => first,
+            None => 0xFFFD,
+        };
+        i += if continuation.is_some() { len } else { 1 };
+        match char::from_u32( code_point ) {
+            Some( c ) => wide.extend( c.encode_utf16( &mut [0; 2] ).iter() ),
+            // an unpaired surrogate
+            None if code_point <= 0xFFFF => wide.push( code_point as u16 ),
+            None => wide.push( 0xFFFD ),
+        }
+    }
+    wide
+}
+
+/// The path for the raw bytes of a name, as written by `to_bytes`.
+pub fn from_bytes( bytes: &[u8] ) -> PathBuf {
+    path_from_bytes( bytes )
+}
+
+#[cfg(unix)]
+fn path_from_bytes( bytes: &[u8] ) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from( std::ffi::OsStr::from_bytes( bytes ) )
+}
+
+#[cfg(windows)]
+fn path_from_bytes( bytes: &[u8] ) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    let mut path = std::ffi::OsString::new();
+    if bytes.starts_with( b"/" ) {
+        path.push( std::path::MAIN_SEPARATOR_STR );
+    }
+    for ( i, c ) in bytes.split( |b| *b == b'/' ).filter( |c| !c.is_empty() ).enumerate() {
+        if i > 0 {
+            path.push( std::path::MAIN_SEPARATOR_STR );
+        }
+        path.push( std::ffi::OsString::from_wide( &wtf8_to_wide( c ) ) );
+    }
+    PathBuf::from( path )
+}
+
+#[cfg(not(any(unix,windows)))]
+fn path_from_bytes( bytes: &[u8] ) -> PathBuf {
+    from_slashes( &String::from_utf8_lossy( bytes ) )
+}
+
+/// Empty components are dropped, only a leading `/` is kept to leave absolute paths absolute.
+fn from_slashes( s: &str ) -> PathBuf {
+    let mut path = String::with_capacity( s.len() );
+    if s.starts_with( '/' ) {
+        path.push( std::path::MAIN_SEPARATOR );
+    }
+    let components: Vec< &str > = s.split( '/' ).filter( |c| !c.is_empty() ).collect();
+    path.push_str( &components.join( std::path::MAIN_SEPARATOR_STR ) );
+    PathBuf::from( path )
+}
+
+/// The raw bytes of `path`, with `/` as separator.
+pub fn to_bytes( path: &Path ) -> Vec< u8 > {
+    let mut bytes = Vec::new();
+    for c in path.components() {
+        if !bytes.is_empty() && bytes.last() != Some( &b'/' ) {
+            bytes.push( b'/' );
+        }
+        match c {
+            Component::RootDir => bytes.push( b'/' ),
+            Component::Normal( c ) => bytes.extend( component_bytes( c ) ),
+            c => bytes.extend( component_bytes( c.as_os_str() ) ),
+        }
+    }
+    bytes
+}
+
+/// Encodes `path` platform independently, for manifests.
+///
+/// Paths are stored relative, with `/` as separator, as plain UTF-8 strings.
+/// Paths that are not valid UTF-8 (or that would be mistaken for an encoded one)
+/// are stored as base64 of their raw bytes, prefixed with `b64:`.
+pub fn encode( path: &Path ) -> String {
+    let bytes = to_bytes( path );
+    match String::from_utf8( bytes ) {
+        Ok( s ) if !s.starts_with( BASE64_PREFIX ) => s,
+        Ok( s ) => format!( "{}{}", BASE64_PREFIX, base64::encode( s.as_bytes() ) ),
+        Err( e ) => format!( "{}{}", BASE64_PREFIX, base64::encode( e.as_bytes() ) ),
+    }
+}
+
+pub fn decode( s: &str ) -> anyhow::Result< PathBuf > {
+    if let Some( encoded ) = s.strip_prefix( BASE64_PREFIX ) {
+        let bytes = base64::decode( encoded )?;
+        Ok( path_from_bytes( &bytes ) )
+    } else {
+        Ok( from_slashes( s ) )
+    }
+}
+
+pub fn serialize< S: Serializer >( path: &Path, serializer: S ) -> Result< S::Ok, S::Error > {
+    serializer.serialize_str( &encode( path ) )
+}
+
+pub fn deserialize< 'de, D: Deserializer< 'de > >( deserializer: D ) -> Result< PathBuf, D::Error > {
+    let s = String::deserialize( deserializer )?;
+    decode( &s ).map_err( serde::de::Error::custom )
+}
+
+/// For lists of paths, use via `#[serde(with = "crate::portable_path::list")]`.
+pub mod list {
+    use std::path::PathBuf;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::ser::SerializeSeq;
+
+    pub fn serialize< S: Serializer >( paths: &[PathBuf], serializer: S ) -> Result< S::Ok, S::Error > {
+        let mut seq = serializer.serialize_seq( Some( paths.len() ) )?;
+        for p in paths {
+            seq.serialize_element( &super::encode( p ) )?;
+        }
+        seq.end()
+    }
+
+    pub fn deserialize< 'de, D: Deserializer< 'de > >( deserializer: D ) -> Result< Vec< PathBuf >, D::Error > {
+        let encoded = Vec::< String >::deserialize( deserializer )?;
+        encoded.iter()
+            .map( |s| super::decode( s ).map_err( serde::de::Error::custom ) )
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip( path: &Path ) {
+        let encoded = encode( path );
+        assert_eq!( decode( &encoded ).unwrap(), path, "encoded as {}", encoded );
+        assert_eq!( from_bytes( &to_bytes( path ) ), path );
+    }
+
+    #[test]
+    fn plain_paths_stay_readable() {
+        assert_eq!( encode( Path::new( "a/b c/d.txt" ) ), "a/b c/d.txt" );
+        assert_eq!( encode( Path::new( "\u{e4}/\u{1f600}" ) ), "\u{e4}/\u{1f600}" );
+        round_trip( Path::new( "a/b c/d.txt" ) );
+        round_trip( Path::new( "\u{e4}/\u{1f600}" ) );
+        round_trip( Path::new( "" ) );
+    }
+
+    #[test]
+    fn prefix_lookalikes_are_encoded() {
+        let encoded = encode( Path::new( "b64:abc" ) );
+        assert!( encoded.starts_with( BASE64_PREFIX ) );
+        assert_ne!( encoded, "b64:abc" );
+        round_trip( Path::new( "b64:abc" ) );
+        round_trip( Path::new( "dir/b64:abc" ) );
+    }
+
+    #[test]
+    fn absolute_paths_stay_absolute() {
+        let root = std::path::MAIN_SEPARATOR.to_string();
+        let absolute = Path::new( &root ).join( "abs" ).join( "x" );
+        assert_eq!( to_bytes( &absolute ), b"/abs/x" );
+        assert_eq!( encode( &absolute ), "/abs/x" );
+        assert!( decode( "/abs/x" ).unwrap().has_root() );
+        round_trip( &absolute );
+        round_trip( Path::new( &root ) );
+    }
+
+    #[test]
+    fn redundant_separators_are_dropped() {
+        assert_eq!( decode( "a//b/" ).unwrap(), Path::new( "a" ).join( "b" ) );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new( std::ffi::OsStr::from_bytes( b"dir/caf\xe9" ) );
+        let encoded = encode( path );
+        assert!( encoded.starts_with( BASE64_PREFIX ) );
+        assert_eq!( to_bytes( path ), b"dir/caf\xe9" );
+        round_trip( path );
+    }
+
+    #[test]
+    fn wtf8_round_trips_unpaired_surrogates() {
+        let wide: Vec< u16 > = vec![ 0x61, 0xD800, 0x62, 0xD83D, 0xDE00, 0xDC00 ];
+        let bytes = wide_to_wtf8( &wide );
+        assert!( String::from_utf8( bytes.clone() ).is_err() );
+        assert_eq!( wtf8_to_wide( &bytes ), wide );
+        assert_eq!( wide_to_wtf8( &[ 0x61, 0xE4 ] ), "a\u{e4}".as_bytes() );
+    }
+}
@@ -0,0 +1,419 @@
+use std::io::{IsTerminal,Write};
+use std::path::{Path,PathBuf};
+use std::sync::mpsc::{channel,Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration,Instant};
+use indicatif::{MultiProgress,ProgressBar,ProgressStyle};
+use serde::Serialize;
+use crate::message::Message;
+use crate::portable_path;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ProgressMode {
+    Bars,
+    Json,
+    Quiet,
+}
+
+impl ProgressMode {
+    pub fn from_name( name: &str ) -> Option< Self > {
+        match name {
+            "bars" => Some( ProgressMode::Bars ),
+            "json" => Some( ProgressMode::Json ),
+            "none" => Some( ProgressMode::Quiet ),
+            _ => None,
+        }
+    }
+
+    /// Bars are only drawn to a terminal, everything else gets log lines instead.
+    pub fn shows_bars( &self ) -> bool {
+        *self == ProgressMode::Bars && std::io::stderr().is_terminal()
+    }
+
+    fn shows_log( &self ) -> bool {
+        *self == ProgressMode::Bars && !std::io::stderr().is_terminal()
+    }
+}
+
+/// Counts the files found while scanning a directory.
+pub struct ScanProgress {
+    bar: ProgressBar,
+    log: bool,
+}
+
+impl ScanProgress {
+    pub fn new( mode: ProgressMode ) -> Self {
+        let bar = if mode.shows_bars() {
+            ProgressBar::new_spinner()
+        } else {
+            ProgressBar::hidden()
+        };
+        bar.set_style(
+            ProgressStyle::default_spinner()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
+            .template("{prefix:.bold.dim} {spinner} {wide_msg} {pos} files found.")
+        );
+        Self {
+            bar,
+            log: mode.shows_log(),
+        }
+    }
+
+    pub fn inc( &self ) {
+        self.bar.inc( 1 );
+    }
+
+    pub fn finish( &self ) {
+        self.bar.finish_at_current_pos();
+        if self.log {
+            eprintln!( "{} files found.", self.bar.position() );
+        }
+    }
+}
+
+/// A bar for work that is counted in steps, e.g. entries compared.
+pub fn step_bar( mode: ProgressMode, what: &str, len: u64 ) -> ProgressBar {
+    if !mode.shows_bars() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new( len );
+    bar.set_style(
+        ProgressStyle::default_bar()
+        .template( "{spinner:.green} {prefix} [{wide_bar:.cyan/blue}] {pos}/{len} {percent}%" )
+    );
+    bar.set_prefix( what );
+    bar
+}
+
+/// How often progress is logged when there is no terminal to draw bars on.
+const LOG_INTERVAL: Duration = Duration::from_secs( 10 );
+
+/// The bars, drawn by the thread joining `MultiProgress`.
+struct Bars {
+    bytes: ProgressBar,
+    files: ProgressBar,
+    /// One line per worker, with the file it is working on.
+    workers: Vec< ( ProgressBar, Option< PathBuf > ) >,
+    draw: JoinHandle< () >,
+}
+
+impl Bars {
+    fn new( what: &str, workers: usize ) -> Self {
+        let multi = MultiProgress::new();
+        let bytes = multi.add( ProgressBar::new( 0 ) );
+        bytes.set_style(
+            ProgressStyle::default_bar()
+            .template( "{spinner:.green} {prefix} [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} at {bytes_per_sec} {percent}% ETA: ~{eta_precise}" )
+        );
+        bytes.set_prefix( what );
+        let files = multi.add( ProgressBar::new( 0 ) );
+        files.set_style(
+            ProgressStyle::default_bar()
+            .template( "{spinner:.green} {prefix} [{wide_bar:.cyan/blue}] {pos}/{len} files" )
+        );
+        files.set_prefix( what );
+        let workers = ( 0..workers ).map( |_| {
+            let worker = multi.add( ProgressBar::new_spinner() );
+            worker.set_style( ProgressStyle::default_spinner().template( "    {wide_msg:.dim}" ) );
+            worker.set_message( "idle" );
+            ( worker, None )
+        }).collect();
+        // `join` only returns when all bars are finished, and only draws while it runs
+        let draw = std::thread::spawn( move || {
+            let _ = multi.join();
+        });
+        Self {
+            bytes,
+            files,
+            workers,
+            draw,
+        }
+    }
+
+    fn started( &mut self, path: &Path ) {
+        if let Some( ( bar, current ) ) = self.workers.iter_mut().find( |( _, current )| current.is_none() ) {
+            bar.set_message( &path.to_string_lossy() );
+            *current = Some( path.to_owned() );
+        }
+    }
+
+    fn stopped( &mut self, path: &Path ) {
+        if let Some( ( bar, current ) ) = self.workers.iter_mut().find( |( _, current )| current.as_deref() == Some( path ) ) {
+            bar.set_message( "idle" );
+            *current = None;
+        }
+    }
+
+    fn finish( self ) {
+        self.bytes.finish();
+        self.files.finish();
+        for ( bar, _ ) in self.workers.iter() {
+            bar.finish_and_clear();
+        }
+        let _ = self.draw.join();
+    }
+}
+
+enum Output {
+    Bars( Bars ),
+    Log( Instant ),
+    Json( JsonProgress ),
+    Quiet,
+}
+
+/// Shows the progress of hashing files, as reported by `Message`s.
+pub struct Progress {
+    what: String,
+    output: Output,
+    start: Instant,
+    bytes: u64,
+    total_bytes: u64,
+    files: u64,
+    total_files: u64,
+    failures: u64,
+}
+
+impl Progress {
+    /// `workers` is the number of files that are worked on at the same time.
+    pub fn new( mode: ProgressMode, json_fd: Option< i32 >, what: &str, workers: usize ) -> Self {
+        let output = match mode {
+            ProgressMode::Bars if mode.shows_bars() => Output::Bars( Bars::new( what, workers ) ),
+            ProgressMode::Bars => Output::Log( Instant::now() ),
+            ProgressMode::Json => match json_fd {
+                #[cfg(unix)]
+                Some( fd ) => Output::Json( JsonProgress::from_fd( fd ).unwrap_or_else( |e| {
+                    eprintln!( "Can not write progress to fd {}: {}", fd, e );
+                    JsonProgress::new( Box::new( std::io::stderr() ) )
+                })),
+                _ => Output::Json( JsonProgress::new( Box::new( std::io::stderr() ) ) ),
+            },
+            ProgressMode::Quiet => Output::Quiet,
+        };
+        Self {
+            what: what.to_string(),
+            output,
+            start: Instant::now(),
+            bytes: 0,
+            total_bytes: 0,
+            files: 0,
+            total_files: 0,
+            failures: 0,
+        }
+    }
+
+    fn log( &self ) {
+        let percent = ( self.bytes * 100 ).checked_div( self.total_bytes ).unwrap_or( 100 );
+        eprintln!( "{}: {}/{} files, {}/{} bytes ({}%) after {}s",
+            self.what, self.files, self.total_files, self.bytes, self.total_bytes, percent, self.start.elapsed().as_secs() );
+    }
+
+    pub fn handle( &mut self, msg: &Message ) {
+        match msg {
+            Message::Started( total_bytes, total_files ) => {
+                self.total_bytes = *total_bytes;
+                self.total_files = *total_files;
+            },
+            Message::Progress( bytes ) => self.bytes += *bytes as u64,
+            Message::FileDone( _, _ ) => self.files += 1,
+            Message::Failure( _, _ ) => self.failures += 1,
+            Message::FileStarted( _, _ ) | Message::Success | Message::Done => {},
+        }
+
+        let mut log = false;
+        match &mut self.output {
+            Output::Bars( bars ) => match msg {
+                Message::Started( total_bytes, total_files ) => {
+                    bars.bytes.set_length( *total_bytes );
+                    bars.files.set_length( *total_files );
+                },
+                Message::Progress( bytes ) => bars.bytes.inc( *bytes as u64 ),
+                Message::FileStarted( path, _ ) => bars.started( path ),
+                Message::FileDone( path, _ ) => {
+                    bars.files.inc( 1 );
+                    bars.stopped( path );
+                },
+                Message::Failure( path, error ) => {
+                    bars.files.println( error );
+                    bars.stopped( path );
+                },
+                Message::Success | Message::Done => {},
+            },
+            Output::Log( last ) => {
+                if let Message::Failure( _, error ) = msg {
+                    eprintln!( "{}", error );
+                }
+                if last.elapsed() >= LOG_INTERVAL {
+                    *last = Instant::now();
+                    log = true;
+                }
+            },
+            Output::Json( json ) => json.handle( msg ),
+            Output::Quiet => {
+                if let Message::Failure( _, error ) = msg {
+                    eprintln!( "{}", error );
+                }
+            },
+        }
+        if log {
+            self.log();
+        }
+    }
+
+    /// Finishes the display, and returns the number of failures.
+    pub fn finish( self ) -> u64 {
+        let failures = self.failures;
+        match self.output {
+            Output::Bars( bars ) => bars.finish(),
+            Output::Log( _ ) => self.log(),
+            Output::Json( _ ) | Output::Quiet => {},
+        }
+        failures
+    }
+
+    /// Handles messages on a thread of its own until `Message::Done`, or until all senders are gone.
+    pub fn spawn( mut self ) -> ( Sender< Message >, JoinHandle< u64 > ) {
+        let ( tx, rx ) = channel();
+        let handle = std::thread::spawn( move || {
+            for msg in rx {
+                self.handle( &msg );
+                if let Message::Done = msg {
+                    break;
+                }
+            }
+            self.finish()
+        });
+        ( tx, handle )
+    }
+}
+
+#[derive(Debug,Serialize)]
+struct ProgressEvent {
+    event: &'static str,
+    /// Seconds since the job started.
+    elapsed: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option< String >,
+    /// The size of the file, for events about a single file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_bytes: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_files: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failures: Option< u64 >,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option< String >,
+}
+
+/// Don't flood the consumer with an event for every block read.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis( 100 );
+
+/// Writes progress messages as JSON lines, e.g. for a GUI driving us.
+pub struct JsonProgress {
+    out: Box< dyn Write + Send >,
+    start: Instant,
+    last_progress: Option< Instant >,
+    bytes: u64,
+    total_bytes: u64,
+    files: u64,
+    total_files: u64,
+    failures: u64,
+}
+
+impl JsonProgress {
+    pub fn new( out: Box< dyn Write + Send > ) -> Self {
+        Self {
+            out,
+            start: Instant::now(),
+            last_progress: None,
+            bytes: 0,
+            total_bytes: 0,
+            files: 0,
+            total_files: 0,
+            failures: 0,
+        }
+    }
+
+    /// Writes to a duplicate of the already open file descriptor `fd`, as passed in by the parent process,
+    /// which stays open for whoever else uses it.
+    #[cfg(unix)]
+    pub fn from_fd( fd: i32 ) -> std::io::Result< Self > {
+        use std::os::unix::io::BorrowedFd;
+        // safety: the fd is handed to us for exactly this, and stays open while we run
+        let fd = unsafe { BorrowedFd::borrow_raw( fd ) };
+        Ok( Self::new( Box::new( std::fs::File::from( fd.try_clone_to_owned()? ) ) ) )
+    }
+
+    fn event( &self, event: &'static str ) -> ProgressEvent {
+        ProgressEvent {
+            event,
+            elapsed: self.start.elapsed().as_secs_f64(),
+            path: None,
+            size: None,
+            bytes: Some( self.bytes ),
+            total_bytes: Some( self.total_bytes ),
+            files: Some( self.files ),
+            total_files: Some( self.total_files ),
+            failures: None,
+            error: None,
+        }
+    }
+
+    fn emit( &mut self, event: &ProgressEvent ) {
+        // progress is best effort, a consumer that went away must not stop the job
+        if let Ok( json ) = serde_json::to_string( event ) {
+            let _ = writeln!( self.out, "{}", json );
+            let _ = self.out.flush();
+        }
+    }
+
+    pub fn handle( &mut self, msg: &Message ) {
+        match msg {
+            Message::Started( total_bytes, total_files ) => {
+                self.total_bytes = *total_bytes;
+                self.total_files = *total_files;
+                let event = self.event( "started" );
+                self.emit( &event );
+            },
+            Message::Progress( bytes ) => {
+                self.bytes += *bytes as u64;
+                if self.last_progress.is_none_or( |l| l.elapsed() >= PROGRESS_INTERVAL ) {
+                    self.last_progress = Some( Instant::now() );
+                    let event = self.event( "progress" );
+                    self.emit( &event );
+                }
+            },
+            Message::FileStarted( path, size ) => {
+                let mut event = self.event( "file_started" );
+                event.path = Some( portable_path::encode( path ) );
+                event.size = Some( *size );
+                self.emit( &event );
+            },
+            Message::FileDone( path, size ) => {
+                self.files += 1;
+                let mut event = self.event( "file_done" );
+                event.path = Some( portable_path::encode( path ) );
+                event.size = Some( *size );
+                self.emit( &event );
+            },
+            Message::Success => {},
+            Message::Failure( path, error ) => {
+                self.failures += 1;
+                let mut event = self.event( "failure" );
+                event.path = Some( portable_path::encode( path ) );
+                event.error = Some( error.clone() );
+                self.emit( &event );
+            },
+            Message::Done => {
+                let mut event = self.event( "done" );
+                event.failures = Some( self.failures );
+                self.emit( &event );
+            },
+        }
+    }
+}
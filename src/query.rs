@@ -0,0 +1,74 @@
+use std::path::Path;
+use rusqlite::types::ValueRef;
+use rusqlite::{params,Connection,NO_PARAMS};
+use crate::archive::load_checksums;
+use crate::command_async::CommandAsync;
+use crate::sqlite_manifest::{is_sqlite_file,store};
+
+use async_trait::async_trait;
+
+/// Runs SQL against manifests, each attached as its own database, see `sqlite_manifest`.
+#[derive(Debug)]
+pub struct Query {
+    sql: String,
+    manifests: Vec< ( String, String ) >,
+}
+
+impl Query {
+    pub fn new( sql: &str ) -> Self {
+        Self {
+            sql: sql.to_string(),
+            manifests: Vec::new(),
+        }
+    }
+
+    /// Makes `checksum_file` available as database `name`, e.g. `SELECT * FROM name.entries`.
+    pub fn add_manifest( &mut self, name: &str, checksum_file: &str ) -> anyhow::Result< () > {
+        let valid = name.chars().next().is_some_and( |c| c.is_ascii_alphabetic() || c == '_' )
+            && name.chars().all( |c| c.is_ascii_alphanumeric() || c == '_' );
+        if !valid {
+            anyhow::bail!( "Invalid manifest name {:?}", name );
+        }
+        self.manifests.push( ( name.to_string(), checksum_file.to_string() ) );
+        Ok(())
+    }
+}
+
+fn format_value( value: ValueRef ) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer( i ) => i.to_string(),
+        ValueRef::Real( f ) => f.to_string(),
+        ValueRef::Text( t ) | ValueRef::Blob( t ) => String::from_utf8_lossy( t ).into_owned(),
+    }
+}
+
+#[async_trait]
+impl CommandAsync for Query {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let conn = Connection::open_in_memory()?;
+        for ( name, checksum_file ) in self.manifests.iter() {
+            if is_sqlite_file( Path::new( checksum_file ) ) {
+                conn.execute( &format!( "ATTACH DATABASE ?1 AS {}", name ), params![ checksum_file ] )?;
+            } else {
+                let checksums = load_checksums( checksum_file )?;
+                conn.execute( &format!( "ATTACH DATABASE ':memory:' AS {}", name ), NO_PARAMS )?;
+                store( &conn, name, &checksums )?;
+            }
+        }
+        // the query must not change the manifests
+        conn.execute_batch( "PRAGMA query_only = ON;" )?;
+
+        let mut statement = conn.prepare( &self.sql )?;
+        let columns: Vec< String > = statement.column_names().iter().map( |c| c.to_string() ).collect();
+        println!( "{}", columns.join( "\t" ) );
+        let mut rows = statement.query( NO_PARAMS )?;
+        while let Some( row ) = rows.next()? {
+            let values = ( 0..columns.len() )
+                .map( |i| row.get_raw_checked( i ).map( format_value ) )
+                .collect::< Result< Vec< String >, _ > >()?;
+            println!( "{}", values.join( "\t" ) );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use std::io::{Read,Write};
+use std::path::Path;
+use rusqlite::{params,Connection,OpenFlags,OptionalExtension,NO_PARAMS};
+use crate::checksums::*;
+use crate::manifest_format::{ManifestReader,ManifestWriter};
+use crate::portable_path;
+
+pub const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+
+/// True if the file at `path` starts like an SQLite database.
+pub fn is_sqlite_file( path: &Path ) -> bool {
+    let mut magic = [ 0u8; 16 ];
+    match std::fs::File::open( path ) {
+        Ok( mut f ) => std::io::Read::read_exact( &mut f, &mut magic ).is_ok() && magic == SQLITE_MAGIC,
+        Err( _ ) => false,
+    }
+}
+
+/// Stores `checksums` in the (attached) database `schema` of `conn`.
+pub fn store( conn: &Connection, schema: &str, checksums: &Checksums ) -> anyhow::Result< () > {
+    conn.execute_batch( &format!( "
+        CREATE TABLE {schema}.meta ( name TEXT PRIMARY KEY, value TEXT );
+        CREATE TABLE {schema}.entries ( path TEXT NOT NULL, size INTEGER NOT NULL, hash TEXT, mtime INTEGER );
+        CREATE INDEX {schema}.entries_path ON entries ( path );
+        CREATE INDEX {schema}.entries_hash ON entries ( hash );
+        BEGIN;
+    ", schema = schema ) )?;
+
+    let mut meta = conn.prepare( &format!( "INSERT INTO {}.meta ( name, value ) VALUES ( ?1, ?2 )", schema ) )?;
+    let entries = checksums.saved_entries();
+    let total_size: u64 = entries.iter().map( |e| e.size() ).sum();
+    meta.execute( params![ "header", serde_json::to_string( &checksums.saved_header() )? ] )?;
+    meta.execute( params![ "algorithm", checksums.algorithm() ] )?;
+    meta.execute( params![ "total_size", total_size.to_string() ] )?;
+
+    let mut insert = conn.prepare( &format!( "INSERT INTO {}.entries ( path, size, hash, mtime ) VALUES ( ?1, ?2, ?3, ?4 )", schema ) )?;
+    for e in entries.iter() {
+        let hash = if e.has_hash() { Some( e.hash() ) } else { None };
+        insert.execute( params![ portable_path::encode( e.path() ), e.size() as i64, hash, e.mtime() ] )?;
+    }
+    conn.execute_batch( "COMMIT;" )?;
+    Ok(())
+}
+
+fn meta( conn: &Connection, name: &str ) -> anyhow::Result< String > {
+    let value: Option< String > = conn.query_row( "SELECT value FROM meta WHERE name = ?1", params![ name ], |row| row.get( 0 ) ).optional()?;
+    match value {
+        Some( value ) => Ok( value ),
+        None => anyhow::bail!( "Missing {} in meta table", name ),
+    }
+}
+
+fn read( conn: &Connection ) -> anyhow::Result< Checksums > {
+    let mut checksums = Checksums::new( &meta( conn, "algorithm" )? );
+    *checksums.header_mut() = serde_json::from_str( &meta( conn, "header" )? )?;
+
+    let mut select = conn.prepare( "SELECT path, size, hash, mtime FROM entries ORDER BY rowid" )?;
+    let mut rows = select.query( NO_PARAMS )?;
+    while let Some( row ) = rows.next()? {
+        let path: String = row.get( 0 )?;
+        let size: i64 = row.get( 1 )?;
+        let hash: Option< String > = row.get( 2 )?;
+        let mtime: Option< i64 > = row.get( 3 )?;
+        let mut e = ChecksumsEntry::new( &portable_path::decode( &path )?, size as u64, &hash.unwrap_or_default() );
+        if let Some( mtime ) = mtime {
+            e.set_mtime( mtime );
+        }
+        checksums.add( e );
+    }
+    Ok( checksums )
+}
+
+pub fn load( path: &Path ) -> anyhow::Result< Checksums > {
+    let conn = Connection::open_with_flags( path, OpenFlags::SQLITE_OPEN_READ_ONLY )?;
+    read( &conn )
+}
+
+/// Manifests stored as SQLite databases, meant to be queried with plain SQL.
+///
+/// The `entries` table holds one row per file with the portable encoded `path`, `size`, `hash`
+/// (`NULL` if there is none) and `mtime` (`NULL` if unknown), indexed by path and hash.
+/// The `meta` table holds the `header` (as JSON), the `algorithm` and the `total_size`.
+///
+/// SQLite only works on files, so everything goes through a temporary one.
+pub struct SqliteFormat;
+
+impl ManifestReader for SqliteFormat {
+    fn read( &self, r: &mut dyn Read ) -> anyhow::Result< Checksums > {
+        let mut file = tempfile::NamedTempFile::new()?;
+        std::io::copy( r, &mut file )?;
+        file.flush()?;
+        load( file.path() )
+    }
+}
+
+impl ManifestWriter for SqliteFormat {
+    fn write( &self, checksums: &Checksums, w: &mut dyn Write ) -> anyhow::Result< () > {
+        let file = tempfile::NamedTempFile::new()?;
+        {
+            let conn = Connection::open( file.path() )?;
+            store( &conn, "main", checksums )?;
+        }
+        std::io::copy( &mut file.reopen()?, w )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::path::{Path,PathBuf};
+use std::time::{Duration,Instant};
+use colored::*;
+use indicatif::{HumanBytes,HumanDuration};
+
+/// The number of largest changes listed in the summary.
+const TOP_CHANGES: usize = 10;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Outcome {
+    Unchanged,
+    Changed,
+    Added,
+    Removed,
+    Corrupted,
+    Hashed,
+    Errored,
+}
+
+impl Outcome {
+    pub fn name( &self ) -> &'static str {
+        match self {
+            Outcome::Unchanged => "unchanged",
+            Outcome::Changed => "changed",
+            Outcome::Added => "added",
+            Outcome::Removed => "removed",
+            Outcome::Corrupted => "corrupted",
+            Outcome::Hashed => "hashed",
+            Outcome::Errored => "errored",
+        }
+    }
+
+    fn color( &self ) -> Color {
+        match self {
+            Outcome::Unchanged | Outcome::Hashed => Color::Green,
+            Outcome::Changed => Color::Yellow,
+            Outcome::Added => Color::Cyan,
+            Outcome::Removed => Color::Red,
+            Outcome::Corrupted | Outcome::Errored => Color::BrightRed,
+        }
+    }
+
+    fn is_change( &self ) -> bool {
+        matches!( self, Outcome::Changed | Outcome::Added | Outcome::Removed | Outcome::Corrupted )
+    }
+}
+
+/// Collects what a command did with every file, and prints it as a table at the end.
+#[derive(Debug)]
+pub struct Summary {
+    start: Instant,
+    files: BTreeMap< Outcome, Vec< ( PathBuf, u64 ) > >,
+    hashed_bytes: u64,
+}
+
+impl Summary {
+    /// Only the given `outcomes` get a row, even when they are empty.
+    pub fn new( outcomes: &[Outcome] ) -> Self {
+        Self {
+            start: Instant::now(),
+            files: outcomes.iter().map( |o| ( *o, Vec::new() ) ).collect(),
+            hashed_bytes: 0,
+        }
+    }
+
+    pub fn add( &mut self, outcome: Outcome, path: &Path, size: u64 ) {
+        self.files.entry( outcome ).or_default().push( ( path.to_owned(), size ) );
+    }
+
+    /// Counts `bytes` read for hashing, for the throughput.
+    pub fn add_hashed( &mut self, bytes: u64 ) {
+        self.hashed_bytes += bytes;
+    }
+
+    pub fn count( &self, outcome: Outcome ) -> usize {
+        self.files.get( &outcome ).map_or( 0, |f| f.len() )
+    }
+
+    /// The files with `outcome`, with their size, in the order they were added.
+    pub fn files( &self, outcome: Outcome ) -> &[ ( PathBuf, u64 ) ] {
+        self.files.get( &outcome ).map_or( &[], |f| f.as_slice() )
+    }
+
+    pub fn print( &self, verbose: bool ) {
+        if !std::io::stdout().is_terminal() {
+            colored::control::set_override( false );
+        }
+        let elapsed = self.start.elapsed();
+
+        println!();
+        println!( "{}", "Summary".bold() );
+        for ( outcome, files ) in self.files.iter() {
+            let size: u64 = files.iter().map( |( _, s )| s ).sum();
+            let line = format!( "  {:<10} {:>10} files {:>12}", outcome.name(), files.len(), HumanBytes( size ).to_string() );
+            if files.is_empty() {
+                println!( "{}", line.dimmed() );
+            } else {
+                println!( "{}", line.color( outcome.color() ) );
+            }
+        }
+        print!( "  Took {}", HumanDuration( elapsed ) );
+        if self.hashed_bytes > 0 {
+            // very short runs would report silly numbers
+            let seconds = elapsed.max( Duration::from_millis( 1 ) ).as_secs_f64();
+            print!( ", hashed {} at {}/s", HumanBytes( self.hashed_bytes ), HumanBytes( ( self.hashed_bytes as f64 / seconds ) as u64 ) );
+        }
+        println!();
+
+        let mut changes: Vec< ( Outcome, &PathBuf, u64 ) > = self.files.iter()
+            .filter( |( o, _ )| o.is_change() )
+            .flat_map( |( o, files )| files.iter().map( move |( p, s )| ( *o, p, *s ) ) )
+            .collect();
+        if !changes.is_empty() {
+            changes.sort_by( |a, b| b.2.cmp( &a.2 ).then_with( || a.1.cmp( b.1 ) ) );
+            println!( "{}", "Largest changes".bold() );
+            for ( outcome, path, size ) in changes.iter().take( TOP_CHANGES ) {
+                println!( "  {} {:>12} {}", format!( "{:<10}", outcome.name() ).color( outcome.color() ), HumanBytes( *size ).to_string(), path.to_string_lossy() );
+            }
+        }
+
+        if verbose {
+            for ( outcome, files ) in self.files.iter().filter( |( _, f )| !f.is_empty() ) {
+                println!( "{}", format!( "{} ({})", outcome.name(), files.len() ).color( outcome.color() ).bold() );
+                let mut files: Vec< &( PathBuf, u64 ) > = files.iter().collect();
+                files.sort();
+                for ( path, size ) in files {
+                    println!( "  {:>12} {}", HumanBytes( *size ).to_string(), path.to_string_lossy() );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::path::{Path,PathBuf};
+use crate::checksums::*;
+use crate::command_async::CommandAsync;
+use crate::compare::ChangeSet;
+use filetime::FileTime;
+
+use async_trait::async_trait;
+
+#[derive(Debug)]
+pub struct Synchronizer {
+    source_dir: PathBuf,
+    target_dir: PathBuf,
+    delete: bool,
+    dry_run: bool,
+}
+
+impl Synchronizer {
+    pub fn new( source_dir: &Path, target_dir: &Path ) -> Self {
+        Self {
+            source_dir: source_dir.to_owned(),
+            target_dir: target_dir.to_owned(),
+            delete: false,
+            dry_run: false,
+        }
+    }
+
+    pub fn set_delete( &mut self, delete: bool ) {
+        self.delete = delete;
+    }
+    pub fn set_dry_run( &mut self, dry_run: bool ) {
+        self.dry_run = dry_run;
+    }
+
+    /// Copies a single file via a temporary file next to the target,
+    /// which is verified against `hash` and then renamed into place.
+    fn copy_file( &self, rp: &Path, hash: &str ) -> anyhow::Result< () > {
+        let source = self.source_dir.join( rp );
+        let target = self.target_dir.join( rp );
+        if let Some( parent ) = target.parent() {
+            std::fs::create_dir_all( parent )?;
+        }
+        let temp = temp_path( &target );
+
+        let result = copy_and_verify( &source, &temp, hash );
+        if result.is_err() {
+            let _ = std::fs::remove_file( &temp );
+            return result;
+        }
+        std::fs::rename( &temp, &target )?;
+        Ok(())
+    }
+}
+
+/// A hidden file next to `target`, used to replace it atomically via rename.
+pub fn temp_path( target: &Path ) -> PathBuf {
+    let mut temp_name = std::ffi::OsString::from( "." );
+    temp_name.push( target.file_name().unwrap_or_default() );
+    temp_name.push( ".folder-compare-tmp" );
+    target.with_file_name( temp_name )
+}
+
+fn copy_and_verify( source: &Path, temp: &Path, hash: &str ) -> anyhow::Result< () > {
+    let metadata = std::fs::metadata( source )?;
+    std::fs::copy( source, temp )?;
+    std::fs::set_permissions( temp, metadata.permissions() )?;
+    filetime::set_file_mtime( temp, FileTime::from_last_modification_time( &metadata ) )?;
+
+    let copied_hash = hash_reader( std::fs::File::open( temp )?, None )?;
+    if copied_hash != hash {
+        anyhow::bail!( "Verification of copy {:?} failed", temp );
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl CommandAsync for Synchronizer {
+    async fn run( &mut self ) -> anyhow::Result<()> {
+        let mut source_checksums = Checksums::new( "sha1" );
+        source_checksums.add_dir( &self.source_dir )?;
+        source_checksums.calculate_hashes( &self.source_dir )?;
+
+        let mut target_checksums = Checksums::new( "sha1" );
+        if self.target_dir.exists() {
+            target_checksums.add_dir( &self.target_dir )?;
+            target_checksums.calculate_hashes( &self.target_dir )?;
+        }
+
+        let change_set = ChangeSet::new( &target_checksums, &source_checksums, None );
+
+        let mut copied = 0;
+        for rp in change_set.added.iter().chain( change_set.changed.iter() ) {
+            if self.dry_run {
+                println!( "copy {}", rp.to_string_lossy() );
+                continue;
+            }
+            let hash = match source_checksums.find( rp ) {
+                Some( e ) => e.hash().to_string(),
+                None => anyhow::bail!( "Lost track of {:?}", rp ),
+            };
+            self.copy_file( rp, &hash )?;
+            copied += 1;
+        }
+
+        let mut deleted = 0;
+        if self.delete {
+            for rp in change_set.removed.iter() {
+                if self.dry_run {
+                    println!( "delete {}", rp.to_string_lossy() );
+                    continue;
+                }
+                std::fs::remove_file( self.target_dir.join( rp ) )?;
+                deleted += 1;
+            }
+        }
+
+        if self.dry_run {
+            println!( "Dry run. Nothing was changed." );
+        } else {
+            println!( "Copied and verified {} files. Deleted {} files.", copied, deleted );
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,57 @@
-use std::error::Error;
+use std::{fmt,error::Error};
+use std::collections::HashSet;
+use std::path::PathBuf;
 use walkdir::WalkDir;
-use std::{fmt,fs};
-use std::path::{Path,PathBuf};
-use std::io::{Read,Write};
+use crate::archive::{add_archive,archive_checksums,load_checksums,ArchiveKind};
+use crate::ci_report::{write_junit,write_sarif};
 use crate::checksums::*;
 use crate::command_async::CommandAsync;
-use indicatif::{ProgressBar,ProgressStyle};
-use sha1::Sha1;
-
-use rayon::prelude::*;
+use crate::html_report::HtmlReport;
+use crate::manifest::PathFilter;
+use crate::compare::{apply_path_matching,write_path_list};
+use crate::message::Message;
+use crate::path_matching::PathMatching;
+use crate::progress::{Progress,ProgressMode,ScanProgress};
+use crate::summary::{Outcome,Summary};
+use crate::watch::{Status,Watch};
 
 use async_trait::async_trait;
-use std::collections::BTreeSet;
+
+/// Process exit code used when `--scrub` found suspected corruption.
+pub const EXIT_SUSPECTED_CORRUPTION: i32 = 3;
+
+/// Returned by a scrub that found files whose content changed while size and mtime did not.
+#[derive(Debug)]
+pub struct SuspectedCorruption( pub usize );
+
+impl fmt::Display for SuspectedCorruption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files are suspected to be corrupted", self.0)
+    }
+}
+impl Error for SuspectedCorruption {}
 
 #[derive(Debug)]
 pub struct Verifier {
     checksum_file: String,
     base_dir: PathBuf,
+    descend_archives: bool,
     changed_file: Option< String >,
     added_file: Option< String >,
     removed_file: Option< String >,
+    corrupted_file: Option< String >,
+    scrub: bool,
+    path_matching: PathMatching,
+    watch: bool,
+    hook: Option< String >,
+    progress: ProgressMode,
+    progress_fd: Option< i32 >,
+    verbose: bool,
+    html_report: Option< String >,
+    junit_file: Option< String >,
+    sarif_file: Option< String >,
+    fail_on_added: bool,
+    filter: PathFilter,
 }
 
 impl Verifier {
@@ -27,9 +59,23 @@ impl Verifier {
         Self {
             checksum_file: checksum_file.to_string(),
             base_dir: base_dir.to_owned(),
+            descend_archives: false,
             changed_file: None,
             added_file: None,
             removed_file: None,
+            corrupted_file: None,
+            scrub: false,
+            path_matching: PathMatching::default(),
+            watch: false,
+            hook: None,
+            progress: ProgressMode::Bars,
+            progress_fd: None,
+            verbose: false,
+            html_report: None,
+            junit_file: None,
+            sarif_file: None,
+            fail_on_added: false,
+            filter: PathFilter::default(),
         }
     }
 
@@ -42,41 +88,105 @@ impl Verifier {
     pub fn set_added_file( &mut self, added_file: &str ) {
         self.added_file = Some( added_file.to_string() )
     }
+    pub fn set_descend_archives( &mut self, descend_archives: bool ) {
+        self.descend_archives = descend_archives;
+    }
+    pub fn set_corrupted_file( &mut self, corrupted_file: &str ) {
+        self.corrupted_file = Some( corrupted_file.to_string() )
+    }
+    pub fn set_scrub( &mut self, scrub: bool ) {
+        self.scrub = scrub;
+    }
+    pub fn set_path_matching( &mut self, path_matching: PathMatching ) {
+        self.path_matching = path_matching;
+    }
+    pub fn set_watch( &mut self, watch: bool ) {
+        self.watch = watch;
+    }
+    pub fn set_hook( &mut self, hook: &str ) {
+        self.hook = Some( hook.to_string() )
+    }
+    pub fn set_progress( &mut self, progress: ProgressMode ) {
+        self.progress = progress;
+    }
+    pub fn set_progress_fd( &mut self, progress_fd: i32 ) {
+        self.progress_fd = Some( progress_fd );
+    }
+    pub fn set_verbose( &mut self, verbose: bool ) {
+        self.verbose = verbose;
+    }
+    pub fn set_html_report( &mut self, html_report: &str ) {
+        self.html_report = Some( html_report.to_string() )
+    }
+    pub fn set_junit_file( &mut self, junit_file: &str ) {
+        self.junit_file = Some( junit_file.to_string() )
+    }
+    pub fn set_sarif_file( &mut self, sarif_file: &str ) {
+        self.sarif_file = Some( sarif_file.to_string() )
+    }
+    /// Reports added files as failures in the JUnit and SARIF output.
+    pub fn set_fail_on_added( &mut self, fail_on_added: bool ) {
+        self.fail_on_added = fail_on_added;
+    }
+    /// Only verifies the files `filter` matches, in the manifest and in the base dir.
+    pub fn set_filter( &mut self, filter: PathFilter ) {
+        self.filter = filter;
+    }
 }
 
 #[async_trait]
 impl CommandAsync for Verifier {
     async fn run( &mut self ) -> anyhow::Result<()> {
-        let old_checksums = Checksums::load( &self.checksum_file )?;
+        let mut summary = Summary::new( &if self.scrub {
+            vec![ Outcome::Unchanged, Outcome::Changed, Outcome::Added, Outcome::Removed, Outcome::Corrupted, Outcome::Errored ]
+        } else {
+            vec![ Outcome::Unchanged, Outcome::Changed, Outcome::Added, Outcome::Removed, Outcome::Errored ]
+        } );
+        let mut old_checksums = load_checksums( &self.checksum_file )?;
+        if !self.filter.is_empty() {
+            old_checksums = self.filter.apply( &old_checksums );
+        }
+        apply_path_matching( &self.checksum_file, &mut old_checksums, self.path_matching );
+        if self.scrub && old_checksums.len() > 0 && !old_checksums.entries().iter().any( |e| e.mtime().is_some() ) {
+            println!( "WARNING: {} has no modification times, scrubbing can not tell corruption from changes", self.checksum_file );
+        }
         let mut new_checksums = Checksums::new( "sha1" );
 
-        let bar = ProgressBar::new( 1_000_000u64 );
-        let spinner_style = ProgressStyle::default_spinner()
-            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
-            .template("{prefix:.bold.dim} {spinner} {wide_msg} {pos} files found.");
-
-        bar.set_style(spinner_style.clone());
-
-        for e in WalkDir::new( &self.base_dir ) {
-            match e {
-                Ok( e ) => {
-                    match e.metadata() {
-                        Ok( m ) => if m.is_file() {
-                            bar.inc( 1 );
-                            let rp = e.path().strip_prefix( &self.base_dir )?;
-                            let ce = ChecksumsEntry::new( rp, m.len(), "" );
-                            new_checksums.add( ce );
+        let scan = ScanProgress::new( self.progress );
+
+        // an archive can be verified without extracting it
+        match ArchiveKind::from_path( &self.base_dir ) {
+            Some( kind ) if self.base_dir.is_file() => {
+                new_checksums = archive_checksums( &self.base_dir, kind )?;
+            },
+            _ => {
+                for e in WalkDir::new( &self.base_dir ) {
+                    match e {
+                        Ok( e ) => {
+                            match e.metadata() {
+                                Ok( m ) => if m.is_file() {
+                                    scan.inc();
+                                    let rp = e.path().strip_prefix( &self.base_dir )?;
+                                    if self.descend_archives {
+                                        add_archive( &mut new_checksums, &self.base_dir, rp, &m );
+                                    } else {
+                                        let ce = ChecksumsEntry::from_metadata( rp, &m );
+                                        new_checksums.add( ce );
+                                    }
+                                },
+                                Err( _e ) => {
+//                                    return Err( Box::new( ChecksumError::Generic( String::from( "Missing metadata" ) ) ) );
+                                },
+                            };
                         },
-                        Err( e ) => {
-//                            return Err( Box::new( ChecksumError::Generic( String::from( "Missing metadata" ) ) ) );
-                        },        
-                    };
-                },
-                Err( e ) => {
-//                    return Err( Box::new( ChecksumError::Generic( String::from( "WalkDir error" ) ) ) );
-                },
-            }
-        };
+                        Err( _e ) => {
+//                            return Err( Box::new( ChecksumError::Generic( String::from( "WalkDir error" ) ) ) );
+                        },
+                    }
+                };
+            },
+        }
+        scan.finish();
 
 //        dbg!( &new_checksums );
 //        println!( "Calculating checksums for {} files. {} bytes total.", new_checksums.len(), new_checksums.total_size() );
@@ -85,59 +195,155 @@ impl CommandAsync for Verifier {
         let mut added = Vec::new();
         let mut changed = Vec::new();
         let mut removed = Vec::new();
-        let mut unchanged = Vec::new();
+        let mut corrupted = Vec::new();
 
         let algorithm = old_checksums.algorithm().to_string();
+        let has_sizes = old_checksums.has_sizes();
 
-        for o in old_checksums.entries() {
-            match new_checksums.find_mut( &o.path() ) {
-                None => {
-                    removed.push( o.path().to_owned() );
-                },
-                Some( n ) => {
-                    if o.size() != n.size() {
-                        changed.push( o.path().to_owned() );
-                    } else {
-                        n.calculate_hash( &self.base_dir, &algorithm, None );
-                        if o.hash() != n.hash() {
-                            changed.push( o.path().to_owned() );
-                        } else {
-                            unchanged.push( o.path().to_owned() );
-                        }
+        if !self.filter.is_empty() {
+            new_checksums = self.filter.apply( &new_checksums );
+        }
+        apply_path_matching( &self.base_dir.to_string_lossy(), &mut new_checksums, self.path_matching );
+
+        // only files that might be unchanged need to be hashed
+        let mut hash_size = 0;
+        let mut hash_files = 0;
+        for n in new_checksums.entries() {
+            if let Some( o ) = old_checksums.find( n.path() ) {
+                let size_changed = has_sizes && o.size() != n.size();
+                if !n.has_hash() && !size_changed {
+                    hash_size += n.size();
+                    hash_files += 1;
+                }
+            }
+        }
+        let ( tx, progress ) = Progress::new( self.progress, self.progress_fd, "Verifying", 1 ).spawn();
+        tx.send( Message::Started( hash_size, hash_files ) )?;
+
+        let mut report = self.html_report.as_ref().map( |_| HtmlReport::new( &format!( "Verifying {} against {}", self.base_dir.to_string_lossy(), self.checksum_file ) ) );
+        // the manifest entries found in the tree, all others were removed
+        let mut found = HashSet::new();
+        for n in new_checksums.entries_mut().iter_mut() {
+            let o = match old_checksums.find( n.path() ) {
+                // added files are reported below, once renames can be detected
+                None => continue,
+                Some( o ) => o,
+            };
+            found.insert( o.path().to_owned() );
+            let outcome = if has_sizes && o.size() != n.size() {
+                Outcome::Changed
+            } else {
+                let hashed = if n.has_hash() {
+                    Ok( () )
+                } else {
+                    let hashed = n.calculate_hash( &self.base_dir, &algorithm, Some( tx.clone() ) );
+                    if hashed.is_ok() {
+                        summary.add_hashed( n.size() );
                     }
+                    hashed
+                };
+                match hashed {
+                    // one unreadable file should not hide the state of all others
+                    Err( e ) => {
+                        tx.send( Message::Failure( n.path().to_owned(), e.to_string() ) )?;
+                        Outcome::Errored
+                    },
+                    Ok( _ ) if o.hash() == n.hash() => Outcome::Unchanged,
+                    // nobody edits a file without touching its mtime
+                    Ok( _ ) if self.scrub && o.mtime().is_some() && o.mtime() == n.mtime() => Outcome::Corrupted,
+                    Ok( _ ) => Outcome::Changed,
+                }
+            };
+            match outcome {
+                Outcome::Changed => changed.push( o.path().to_owned() ),
+                Outcome::Corrupted => corrupted.push( o.path().to_owned() ),
+                _ => {},
+            }
+            summary.add( outcome, n.path(), n.size() );
+            if let Some( report ) = &mut report {
+                report.add( outcome, n.path(), Some( &o ), Some( n ) );
+            }
+        }
+
+        tx.send( Message::Done )?;
+        let _ = progress.join();
+
+        let mut removed_sizes = HashSet::new();
+        for o in old_checksums.entries().iter().filter( |o| !found.contains( o.path() ) ) {
+            removed.push( o.path().to_owned() );
+            removed_sizes.insert( o.size() );
+            summary.add( Outcome::Removed, o.path(), o.size() );
+            if let Some( report ) = &mut report {
+                report.add( Outcome::Removed, o.path(), Some( o ), None );
+            }
+        }
+
+        // renames in the report can only be found with the hashes of added files
+        if report.is_some() {
+            for n in new_checksums.entries_mut().iter_mut() {
+                if !n.has_hash() && removed_sizes.contains( &n.size() ) && old_checksums.find( n.path() ).is_none() {
+                    let _ = n.calculate_hash( &self.base_dir, &algorithm, None );
                 }
             }
         }
 
         for n in new_checksums.entries() {
-            match old_checksums.find( &n.path() ) {
-                Some( _ ) => {},
-                None => added.push( n.path().to_owned() ),
+            if old_checksums.find( n.path() ).is_none() {
+                added.push( n.path().to_owned() );
+                summary.add( Outcome::Added, n.path(), n.size() );
+                if let Some( report ) = &mut report {
+                    report.add( Outcome::Added, n.path(), None, Some( n ) );
+                }
             }
         }
+        // the tree is walked in directory order
+        changed.sort();
+        corrupted.sort();
+        added.sort();
 
-        dbg!(&unchanged);
-        dbg!(&changed);
-        dbg!(&removed);
-        dbg!(&added);
+        summary.print( self.verbose );
+        if let ( Some( report ), Some( html_report ) ) = ( &report, &self.html_report ) {
+            report.save( html_report )?;
+        }
+        if let Some( junit_file ) = &self.junit_file {
+            write_junit( junit_file, &self.checksum_file, &summary, self.fail_on_added )?;
+        }
+        if let Some( sarif_file ) = &self.sarif_file {
+            write_sarif( sarif_file, &self.base_dir, &summary, self.fail_on_added )?;
+        }
 
         if let Some( changed_file ) = &self.changed_file {
-            let mut f = std::fs::File::create(&changed_file).expect("create file failed for changed file");
-            for e in changed {
-                f.write_all(format!("{}\n", e.to_string_lossy() ).as_bytes());
-            }
+            write_path_list( changed_file, &changed )?;
         }
         if let Some( removed_file ) = &self.removed_file {
-            let mut f = std::fs::File::create(&removed_file).expect("create file failed for removed file");
-            for e in removed {
-                f.write_all(format!("{}\n", e.to_string_lossy() ).as_bytes());
-            }
+            write_path_list( removed_file, &removed )?;
         }
         if let Some( added_file ) = &self.added_file {
-            let mut f = std::fs::File::create(&added_file).expect("create file failed for added file");
-            for e in added {
-                f.write_all(format!("{}\n", e.to_string_lossy() ).as_bytes());
+            write_path_list( added_file, &added )?;
+        }
+        if let Some( corrupted_file ) = &self.corrupted_file {
+            write_path_list( corrupted_file, &corrupted )?;
+        }
+
+        if self.watch {
+            if !self.base_dir.is_dir() {
+                anyhow::bail!( "Only directories can be watched" );
             }
+            let mut watch = Watch::new( &self.base_dir, &old_checksums );
+            if let Some( hook ) = &self.hook {
+                watch.set_hook( hook );
+            }
+            watch.set_status( &changed, Status::Changed )?;
+            watch.set_status( &corrupted, Status::Changed )?;
+            watch.set_status( &removed, Status::Removed )?;
+            watch.set_status( &added, Status::Added )?;
+            watch.run()?;
+        }
+        if !corrupted.is_empty() {
+            return Err( SuspectedCorruption( corrupted.len() ).into() );
+        }
+        if summary.count( Outcome::Errored ) > 0 {
+            anyhow::bail!( "{} files could not be verified", summary.count( Outcome::Errored ) );
         }
         Ok(())
     }
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap,BTreeSet};
+use std::path::{Path,PathBuf};
+use std::io::Write;
+use std::process::{Command,Stdio};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use serde::Serialize;
+use walkdir::WalkDir;
+use notify::{DebouncedEvent,RecursiveMode,Watcher};
+use crate::checksums::*;
+use crate::portable_path;
+
+/// The state of a single path compared to the manifest.
+#[derive(Debug,Clone,Copy,PartialEq,Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Unchanged,
+    Changed,
+    Added,
+    Removed,
+}
+
+#[derive(Debug,Serialize)]
+struct Event {
+    event: Status,
+    path: String,
+}
+
+/// Keeps track of the state of a tree compared to a manifest,
+/// and reports every change of state as it happens.
+#[derive(Debug)]
+pub struct Watch< 'a > {
+    base_dir: PathBuf,
+    manifest: &'a Checksums,
+    hook: Option< String >,
+    /// The paths of the manifest, sorted so everything below a directory is one range.
+    known: BTreeSet< PathBuf >,
+    status: BTreeMap< PathBuf, Status >,
+}
+
+impl< 'a > Watch< 'a > {
+    pub fn new( base_dir: &Path, manifest: &'a Checksums ) -> Self {
+        Self {
+            base_dir: base_dir.to_owned(),
+            manifest,
+            hook: None,
+            known: manifest.entries().iter().map( |e| e.path().to_owned() ).collect(),
+            status: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_hook( &mut self, hook: &str ) {
+        self.hook = Some( hook.to_string() )
+    }
+
+    /// Reports the result of the initial verify.
+    pub fn set_status( &mut self, paths: &[PathBuf], status: Status ) -> anyhow::Result< () > {
+        for p in paths {
+            self.update( p, status )?;
+        }
+        Ok(())
+    }
+
+    /// Blocks forever, rehashing every file touched below the base dir.
+    pub fn run( &mut self ) -> anyhow::Result< () > {
+        let ( tx, rx ) = channel();
+        let mut watcher = notify::watcher( tx, Duration::from_millis( 500 ) )?;
+        watcher.watch( &self.base_dir, RecursiveMode::Recursive )?;
+
+        loop {
+            match rx.recv()? {
+                DebouncedEvent::Create( p ) | DebouncedEvent::Write( p ) | DebouncedEvent::Chmod( p ) | DebouncedEvent::Remove( p ) => {
+                    self.recheck( &p );
+                },
+                DebouncedEvent::Rename( from, to ) => {
+                    self.recheck( &from );
+                    self.recheck( &to );
+                },
+                DebouncedEvent::Rescan => {
+                    self.recheck( &self.base_dir.clone() );
+                },
+                DebouncedEvent::Error( e, p ) => {
+                    eprintln!( "Watch error {:?}: {}", p, e );
+                },
+                DebouncedEvent::NoticeWrite( _ ) | DebouncedEvent::NoticeRemove( _ ) => {},
+            }
+        }
+    }
+
+    /// Like `check_path`, but a single failure must not end the watch.
+    fn recheck( &mut self, fullpath: &Path ) {
+        if let Err( e ) = self.check_path( fullpath ) {
+            eprintln!( "Failed checking {:?}: {}", fullpath, e );
+        }
+    }
+
+    /// Rechecks `fullpath`, and everything below it, if it is a directory.
+    fn check_path( &mut self, fullpath: &Path ) -> anyhow::Result< () > {
+        let rp = match fullpath.strip_prefix( &self.base_dir ) {
+            Ok( rp ) => rp.to_owned(),
+            Err( _ ) => return Ok(()),
+        };
+
+        // everything we know of below the path might be gone
+        let below = |p: &&PathBuf| p.starts_with( &rp );
+        let mut known: Vec< PathBuf > = self.known.range( rp.clone().. ).take_while( below )
+            .chain( self.status.range( rp.clone().. ).map( |( p, _ )| p ).take_while( below ) )
+            .cloned()
+            .collect();
+        known.sort();
+        known.dedup();
+        for p in known {
+            if !self.base_dir.join( &p ).is_file() {
+                if let Err( e ) = self.check_file( &p ) {
+                    eprintln!( "Failed checking {:?}: {}", p, e );
+                }
+            }
+        }
+
+        if fullpath.exists() {
+            for e in WalkDir::new( fullpath ) {
+                let e = match e {
+                    Ok( e ) => e,
+                    Err( e ) => {
+                        eprintln!( "Failed checking {:?}: {}", e.path().unwrap_or( fullpath ), e );
+                        continue;
+                    },
+                };
+                if e.file_type().is_file() {
+                    let rp = e.path().strip_prefix( &self.base_dir )?.to_owned();
+                    if let Err( e ) = self.check_file( &rp ) {
+                        eprintln!( "Failed checking {:?}: {}", rp, e );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_file( &mut self, rp: &Path ) -> anyhow::Result< () > {
+        let fullpath = self.base_dir.join( rp );
+        let expected = self.manifest.find( rp );
+        let status = match ( std::fs::metadata( &fullpath ), expected ) {
+            ( Ok( m ), Some( o ) ) if m.is_file() => {
+                let mut n = ChecksumsEntry::from_metadata( rp, &m );
+                if self.manifest.has_sizes() && o.size() != n.size() {
+                    Status::Changed
+                } else {
+                    match n.calculate_hash( &self.base_dir, self.manifest.algorithm(), None ) {
+                        Ok( _ ) if n.hash() == o.hash() => Status::Unchanged,
+                        Ok( _ ) => Status::Changed,
+                        // vanished while we were looking at it, we will get another event
+                        Err( _ ) => return Ok(()),
+                    }
+                }
+            },
+            ( Ok( m ), None ) if m.is_file() => Status::Added,
+            ( _, Some( _ ) ) => Status::Removed,
+            ( _, None ) => Status::Unchanged,
+        };
+        self.update( rp, status )
+    }
+
+    fn update( &mut self, rp: &Path, status: Status ) -> anyhow::Result< () > {
+        let previous = self.status.get( rp ).copied().unwrap_or( Status::Unchanged );
+        if previous == status {
+            return Ok(());
+        }
+        if status == Status::Unchanged {
+            self.status.remove( rp );
+        } else {
+            self.status.insert( rp.to_owned(), status );
+        }
+        self.emit( rp, status )
+    }
+
+    fn emit( &self, rp: &Path, status: Status ) -> anyhow::Result< () > {
+        let json = serde_json::to_string( &Event { event: status, path: portable_path::encode( rp ) } )?;
+        match &self.hook {
+            None => {
+                println!( "{}", json );
+                std::io::stdout().flush()?;
+            },
+            Some( hook ) => {
+                let mut child = shell( hook )
+                    .env( "FOLDER_COMPARE_EVENT", serde_json::to_value( status )?.as_str().unwrap_or_default() )
+                    .env( "FOLDER_COMPARE_PATH", rp )
+                    .stdin( Stdio::piped() )
+                    .spawn()?;
+                if let Some( mut stdin ) = child.stdin.take() {
+                    let _ = writeln!( stdin, "{}", json );
+                }
+                let exit = child.wait()?;
+                if !exit.success() {
+                    eprintln!( "Hook failed for {:?}: {}", rp, exit );
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+fn shell( command: &str ) -> Command {
+    let mut c = Command::new( "cmd" );
+    c.arg( "/C" ).arg( command );
+    c
+}
+
+#[cfg(not(windows))]
+fn shell( command: &str ) -> Command {
+    let mut c = Command::new( "sh" );
+    c.arg( "-c" ).arg( command );
+    c
+}